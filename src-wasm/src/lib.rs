@@ -1,8 +1,110 @@
-use loro::LoroDoc;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::ops::ControlFlow;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex};
+
+use base64::Engine as _;
+use loro::{
+    awareness::EphemeralStore,
+    cursor::{Cursor, PosType, Side},
+    event::{Diff, DiffBatch, DiffEvent},
+    CommitOptions, Container, ContainerTrait, ContainerType, ExportMode, Frontiers, ImportStatus,
+    JsonMapOp, JsonOpContent, JsonTextOp, LoroDoc, LoroEncodeError, LoroError, LoroMap, LoroText,
+    LoroValue, Subscription, TextDelta, UndoManager, ValueOrContainer, VersionRange, VersionVector,
+    ID,
+};
 use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
 use wasm_bindgen::prelude::*;
 
+/// Name of the text container the single-container API operates on, unless
+/// the engine was built with `with_container_name`
+const DEFAULT_CONTAINER: &str = "content";
+
+/// Content of a document created with `KernEngine::new`
+const WELCOME_TEXT: &str = "# Welcome to Kern\n\nStart typing...";
+
+/// Leading bytes of blobs written by `export_snapshot`
+const SNAPSHOT_MAGIC: &[u8] = b"KERN";
+
+/// Envelope format version following `SNAPSHOT_MAGIC`
+///
+/// Bump this when the saved layout changes and teach `upgrade` to rewrite the
+/// previous version. Raw Loro exports from before the envelope are version 0.
+const SNAPSHOT_VERSION: u8 = 2;
+
+/// Leading bytes of every raw Loro export, such as `export_updates` output
+const LORO_MAGIC: &[u8] = b"loro";
+
+/// Leading byte of blobs written by `export_snapshot_compressed`
+///
+/// Raw Loro exports start with `loro` and snapshots with `KERN`, so this can't collide.
+const COMPRESSED_MAGIC: u8 = 0x01;
+
+/// Map container holding named checkpoint frontiers
+const CHECKPOINTS: &str = "checkpoints";
+
+/// Map container holding non-paragraph block types, keyed by line ID
+const BLOCKS: &str = "blocks";
+
+/// Map container holding review comments, keyed by comment ID
+const COMMENTS: &str = "comments";
+
+/// Map container holding engine bookkeeping, such as the version clock
+const META: &str = "meta";
+
+/// Key in `META` under which the version clock is persisted
+const VERSION_KEY: &str = "version";
+
+/// Key in `META` holding the document's creation time in Unix seconds
+const CREATED_AT_KEY: &str = "created_at";
+
+/// Key in `META` holding the number of local commits made by any peer
+///
+/// Each peer writes its count under this key suffixed with `:` and its peer
+/// ID, so concurrent writes never overwrite each other; the count is the
+/// largest entry. The bare key is read for documents saved before that.
+const REVISIONS_KEY: &str = "revisions";
+
+/// Block types accepted by `set_block_type`
+const BLOCK_TYPES: [&str; 4] = ["heading", "paragraph", "list-item", "code"];
+
+/// Commit origin for bookkeeping writes that shouldn't be undoable
+const META_ORIGIN: &str = "kern-meta";
+
+/// Ops a mark or unmark adds to the oplog, one anchor at each end
+const MARK_OPS: usize = 2;
+
+/// History below this many ops is never worth compacting
+const COMPACT_MIN_OPS: usize = 10_000;
+
+/// Suggest compaction once history holds this many ops per live character
+const COMPACT_OPS_PER_CHAR: usize = 4;
+
+/// Trace entries `diff_ops` may keep before settling for a coarser script
+///
+/// The trace grows with the square of the edit distance, so loading a large
+/// document over a small one would otherwise take gigabytes.
+const DIFF_BUDGET: usize = 1 << 22;
+
+/// Served views `view_patch` remembers; clients polling often need only one
+const SERVED_VIEWS: usize = 4;
+
+/// Peers whose awareness hasn't been refreshed within this window are pruned
+const AWARENESS_TIMEOUT_MS: i64 = 30_000;
+
+/// Awareness key prefix for selections, followed by the peer ID
+const SELECTION_KEY: &str = "selection:";
+
 /// Initialize panic hook for better error messages in browser console
+///
+/// Safe to call again, e.g. when the module is initialized twice. This hook
+/// is the only module-wide state: every `KernEngine` owns its document,
+/// peer ID and subscriptions.
 #[wasm_bindgen(start)]
 pub fn init() {
     console_error_panic_hook::set_once();
@@ -14,20 +116,317 @@ pub fn check_health() -> String {
     "Kern Engine: Active (Rust/WASM)".into()
 }
 
+/// Diff the content of two snapshots as `[{ line, kind }]`
+///
+/// An empty byte slice stands for an empty document.
+#[wasm_bindgen]
+pub fn diff_snapshots(old: &[u8], new: &[u8]) -> Result<JsValue, KernError> {
+    Ok(serde_wasm_bindgen::to_value(&snapshot_diff(old, new)?)?)
+}
+
+/// Rewrite a saved blob of any supported format version as a current one
+#[wasm_bindgen]
+pub fn migrate(data: &[u8]) -> Result<Vec<u8>, KernError> {
+    let payload = upgraded_payload(data)?;
+    let mut blob = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 1 + payload.len());
+    blob.extend_from_slice(SNAPSHOT_MAGIC);
+    blob.push(SNAPSHOT_VERSION);
+    blob.extend_from_slice(&payload);
+    Ok(blob)
+}
+
+/// The metadata header of a blob from `export_snapshot_with_meta`, or null
+///
+/// Only the header is parsed; the document itself is not imported.
+#[wasm_bindgen]
+pub fn read_snapshot_meta(data: &[u8]) -> Result<JsValue, KernError> {
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    Ok(snapshot_meta(data)?.serialize(&serializer)?)
+}
+
+/// The metadata header of a blob, `None` for blobs saved without one
+pub fn snapshot_meta(data: &[u8]) -> Result<Option<SnapshotMeta>, KernError> {
+    let payload = upgraded_payload(data)?;
+    let (header, _) = split_header(&payload)?;
+    if header.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_slice(header)
+        .map(Some)
+        .map_err(|_| KernError::NotKernSnapshot)
+}
+
+/// The first `max_lines` lines of a saved blob, for previews
+///
+/// The blob is loaded into a throwaway document, so listing many documents
+/// doesn't keep an engine alive for each.
+#[wasm_bindgen]
+pub fn snapshot_to_view(data: &[u8], max_lines: usize) -> Result<JsValue, KernError> {
+    Ok(serde_wasm_bindgen::to_value(&snapshot_view(
+        data, max_lines,
+    )?)?)
+}
+
+/// The view of the first `max_lines` lines of a saved blob
+pub fn snapshot_view(data: &[u8], max_lines: usize) -> Result<DocumentView, KernError> {
+    let doc = LoroDoc::new();
+    doc.import(&open_blob(data)?)?;
+    Ok(KernCore::from_doc(doc, DEFAULT_CONTAINER).view_range(0, max_lines))
+}
+
+/// Line-level changes between the content of two snapshots
+pub fn snapshot_diff(old: &[u8], new: &[u8]) -> Result<Vec<LineChange>, KernError> {
+    let content = |bytes: &[u8]| -> Result<String, KernError> {
+        let doc = LoroDoc::new();
+        if !bytes.is_empty() {
+            doc.import(&open_blob(bytes)?)?;
+        }
+        Ok(doc.get_text(DEFAULT_CONTAINER).to_string())
+    };
+    Ok(line_diff(&content(old)?, &content(new)?))
+}
+
+/// Errors surfaced by the engine instead of panicking
+#[derive(Debug)]
+pub enum KernError {
+    /// A Loro document operation failed
+    Loro(LoroError),
+    /// Loro could not encode an export
+    Encode(LoroEncodeError),
+    /// A value could not be converted to or from JS
+    Serialization(String),
+    /// An edit delta from JS didn't have the `EditDelta` shape
+    InvalidDelta(String),
+    /// A peer ID string was not a valid u64
+    InvalidPeerId(String),
+    /// The peer ID can't change while local ops are uncommitted
+    PendingOps,
+    /// The document is checked out to a past version and is read-only
+    Detached,
+    /// Local edits are disabled by `set_readonly`
+    ReadOnly,
+    /// A position lies outside the document
+    OutOfRange(String),
+    /// An edit or import would grow the document past its `Limits`
+    LimitExceeded(String),
+    /// Delta `index` of a batch failed, so none of the batch was applied
+    BatchFailed { index: usize, error: Box<KernError> },
+    /// A compressed blob could not be inflated
+    Decompress(String),
+    /// A string passed as base64 is not valid standard base64
+    InvalidBase64(String),
+    /// No checkpoint with this name exists
+    UnknownCheckpoint(String),
+    /// No text container with this name exists
+    UnknownContainer(String),
+    /// No comment with this ID exists
+    UnknownComment(String),
+    /// Marks were applied to a container created as plain text
+    PlainContainer(String),
+    /// A cursor could not be created, decoded or resolved
+    InvalidCursor(String),
+    /// An awareness update could not be decoded
+    InvalidAwareness(String),
+    /// A line-ending style other than `"lf"` or `"crlf"` was requested
+    InvalidLineEnding(String),
+    /// A block type outside `BLOCK_TYPES` was requested
+    InvalidBlockType(String),
+    /// A JSON document passed to `from_json` is malformed
+    InvalidDocument(String),
+    /// A frontier refers to ops this document hasn't seen
+    UnknownFrontier,
+    /// A blob is neither a Kern snapshot nor a Loro export
+    NotKernSnapshot,
+    /// A Kern snapshot was written by a newer, unknown format version
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for KernError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernError::Loro(e) => write!(f, "{e}"),
+            KernError::Encode(e) => write!(f, "{e}"),
+            KernError::Serialization(msg) => write!(f, "Serialization error: {msg}"),
+            KernError::InvalidDelta(msg) => write!(f, "Invalid edit delta: {msg}"),
+            KernError::InvalidPeerId(id) => write!(f, "Invalid peer ID: {id}"),
+            KernError::PendingOps => write!(f, "Cannot change peer ID with pending local ops"),
+            KernError::Detached => write!(f, "Cannot edit while checked out to a past version"),
+            KernError::ReadOnly => write!(f, "Document is read-only"),
+            KernError::OutOfRange(msg) => write!(f, "Out of range: {msg}"),
+            KernError::LimitExceeded(msg) => write!(f, "Limit exceeded: {msg}"),
+            KernError::BatchFailed { index, error } => write!(f, "Edit {index} of batch: {error}"),
+            KernError::Decompress(msg) => write!(f, "Decompression failed: {msg}"),
+            KernError::InvalidBase64(msg) => write!(f, "Invalid base64: {msg}"),
+            KernError::UnknownCheckpoint(name) => write!(f, "No checkpoint named {name:?}"),
+            KernError::UnknownContainer(name) => write!(f, "No text container named {name:?}"),
+            KernError::UnknownComment(id) => write!(f, "No comment with ID {id:?}"),
+            KernError::PlainContainer(name) => {
+                write!(
+                    f,
+                    "Text container {name:?} is plain text and takes no marks"
+                )
+            }
+            KernError::InvalidCursor(msg) => write!(f, "Invalid cursor: {msg}"),
+            KernError::InvalidAwareness(msg) => write!(f, "Invalid awareness update: {msg}"),
+            KernError::InvalidLineEnding(style) => write!(f, "Unknown line ending {style:?}"),
+            KernError::InvalidBlockType(kind) => write!(f, "Unknown block type {kind:?}"),
+            KernError::InvalidDocument(msg) => write!(f, "Invalid JSON document: {msg}"),
+            KernError::UnknownFrontier => write!(f, "Frontier is not part of this document"),
+            KernError::NotKernSnapshot => write!(f, "Not a Kern snapshot"),
+            KernError::UnsupportedVersion(v) => write!(f, "Unsupported snapshot version {v}"),
+        }
+    }
+}
+
+impl std::error::Error for KernError {}
+
+impl KernError {
+    /// Stable, machine-readable name of the error kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            KernError::Loro(e) => match e {
+                LoroError::OutOfBound { .. } | LoroError::EndIndexLessThanStartIndex { .. } => {
+                    "OUT_OF_RANGE"
+                }
+                LoroError::EditWhenDetached => "DETACHED",
+                LoroError::DecodeError(_)
+                | LoroError::DecodeVersionVectorError
+                | LoroError::DecodeDataCorruptionError
+                | LoroError::DecodeChecksumMismatchError
+                | LoroError::IncompatibleFutureEncodingError(_)
+                | LoroError::ImportUnsupportedEncodingMode
+                | LoroError::ImportUpdatesThatDependsOnOutdatedVersion => "IMPORT_FAILED",
+                _ => "DOCUMENT_ERROR",
+            },
+            KernError::Encode(_) => "EXPORT_FAILED",
+            KernError::Serialization(_) => "SERIALIZATION",
+            KernError::InvalidDelta(_) => "INVALID_DELTA",
+            KernError::InvalidPeerId(_) => "INVALID_PEER_ID",
+            KernError::PendingOps => "PENDING_OPS",
+            KernError::Detached => "DETACHED",
+            KernError::ReadOnly => "READ_ONLY",
+            KernError::OutOfRange(_) => "OUT_OF_RANGE",
+            KernError::LimitExceeded(_) => "LIMIT_EXCEEDED",
+            KernError::BatchFailed { error, .. } => error.code(),
+            KernError::Decompress(_) | KernError::NotKernSnapshot => "IMPORT_FAILED",
+            KernError::InvalidBase64(_) => "INVALID_BASE64",
+            KernError::UnknownCheckpoint(_) => "UNKNOWN_CHECKPOINT",
+            KernError::UnknownContainer(_) => "UNKNOWN_CONTAINER",
+            KernError::UnknownComment(_) => "UNKNOWN_COMMENT",
+            KernError::PlainContainer(_) => "PLAIN_CONTAINER",
+            KernError::InvalidCursor(_) => "INVALID_CURSOR",
+            KernError::InvalidAwareness(_) => "INVALID_AWARENESS",
+            KernError::InvalidLineEnding(_) => "INVALID_LINE_ENDING",
+            KernError::InvalidBlockType(_) => "INVALID_BLOCK_TYPE",
+            KernError::InvalidDocument(_) => "INVALID_DOCUMENT",
+            KernError::UnknownFrontier => "UNKNOWN_FRONTIER",
+            KernError::UnsupportedVersion(_) => "UNSUPPORTED_VERSION",
+        }
+    }
+}
+
+/// The shape errors take on the JS side, so callers can branch on `code`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DocumentError {
+    pub code: String,
+    /// Human-readable English description
+    pub message: String,
+    /// Position of the offending edit when a batch was rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+}
+
+impl From<&KernError> for DocumentError {
+    fn from(e: &KernError) -> Self {
+        DocumentError {
+            code: e.code().to_string(),
+            message: e.to_string(),
+            index: match e {
+                KernError::BatchFailed { index, .. } => Some(*index),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<LoroError> for KernError {
+    fn from(e: LoroError) -> Self {
+        KernError::Loro(e)
+    }
+}
+
+impl From<LoroEncodeError> for KernError {
+    fn from(e: LoroEncodeError) -> Self {
+        KernError::Encode(e)
+    }
+}
+
+impl From<serde_wasm_bindgen::Error> for KernError {
+    fn from(e: serde_wasm_bindgen::Error) -> Self {
+        KernError::Serialization(e.to_string())
+    }
+}
+
+impl From<KernError> for JsValue {
+    fn from(e: KernError) -> Self {
+        serde_wasm_bindgen::to_value(&DocumentError::from(&e))
+            .unwrap_or_else(|_| JsValue::from_str(&e.to_string()))
+    }
+}
+
 /// Edit operation delta from JS
-#[derive(Serialize, Deserialize, Debug)]
+///
+/// `col` (and `delete`) are measured in Unicode scalar values, the same unit
+/// Loro's text container uses for positions - not UTF-8 bytes or UTF-16 units.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EditDelta {
+    /// Must name an existing line; a trailing empty line after `\n` counts.
+    /// The line count itself appends: inserting there adds a new last line.
     pub line: usize,
+    /// Clamped to the end of the line
     pub col: usize,
+    /// Inserted where the caret ends up after `delete`, so a delta with both
+    /// replaces the deleted characters, like typing over a selection
     pub insert: Option<String>,
+    /// Characters to remove *before* the caret (backspace semantics), capped
+    /// at the start of the document; applied before `insert`
     pub delete: Option<usize>,
 }
 
 /// Line representation for view
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct LineView {
+    /// Stable identity of the line, unaffected by edits to other lines
     pub id: String,
     pub content: String,
+    /// Length of `content` in Unicode scalar values
+    #[serde(default)]
+    pub char_len: usize,
+    /// Whether any formatting mark touches this line
+    #[serde(default)]
+    pub styled: bool,
+    /// Formatting spans on this line, omitted when the line is unstyled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marks: Option<Vec<MarkSpan>>,
+    /// Block type: `heading`, `paragraph`, `list-item` or `code`
+    #[serde(default = "default_block")]
+    pub block: String,
+    /// Rows the line occupies when word-wrapped, if a wrap width was given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visual_rows: Option<usize>,
+}
+
+fn default_block() -> String {
+    "paragraph".to_string()
+}
+
+/// A formatting span within a line, in line-relative character columns
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MarkSpan {
+    pub start: usize,
+    pub end: usize,
+    pub key: String,
+    pub value: LoroValue,
 }
 
 /// Document view snapshot
@@ -35,154 +434,8264 @@ pub struct LineView {
 pub struct DocumentView {
     pub lines: Vec<LineView>,
     pub version: u64,
+    /// Line count of the whole document, even when `lines` is a slice
+    #[serde(default)]
+    pub total_lines: usize,
+}
+
+impl DocumentView {
+    /// Annotate every line with its `visual_rows` at `wrap_width` columns;
+    /// `None` leaves the view as is
+    pub fn wrapped(mut self, wrap_width: Option<usize>) -> Self {
+        if let Some(width) = wrap_width {
+            for line in &mut self.lines {
+                line.visual_rows = Some(visual_rows(&line.content, width));
+            }
+        }
+        self
+    }
+}
+
+/// The structured content of a document, for storage that isn't Loro-native
+///
+/// Holds no history, so it can't be merged with the document it came from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DocumentJson {
+    pub lines: Vec<JsonLine>,
+    /// Version clock at export, so a rebuilt document never goes back in time
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// One line of a `DocumentJson`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JsonLine {
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub marks: Vec<MarkSpan>,
+    #[serde(default = "default_block")]
+    pub block: String,
+}
+
+/// One step turning a client's cached view lines into the current ones
+///
+/// Steps apply in order, each `index` referring to the lines as left by the
+/// previous step.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum LineOp {
+    Insert { index: usize, line: LineView },
+    Delete { index: usize },
+    Replace { index: usize, line: LineView },
+}
+
+/// Changes since a client's cached view, as returned by `get_view_patch`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ViewPatch {
+    pub version: u64,
+    /// The cached version was unknown, so `lines` holds the whole view
+    pub full: bool,
+    pub ops: Vec<LineOp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<Vec<LineView>>,
+}
+
+/// Lines an edit or import touched, for splicing a rendered line list
+///
+/// The `removed` old lines starting at `start` were replaced by the current
+/// lines `[start, end)`, of which there are `inserted`. All zero when
+/// nothing changed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+    pub inserted: usize,
+    pub removed: usize,
+}
+
+impl LineRange {
+    /// The span of lines between the first and last that differ
+    fn between(old: &str, new: &str) -> Self {
+        let old: Vec<&str> = old.split('\n').collect();
+        let new: Vec<&str> = new.split('\n').collect();
+
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        if prefix == old.len() && prefix == new.len() {
+            return LineRange::default();
+        }
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        LineRange {
+            start: prefix,
+            end: new.len() - suffix,
+            inserted: new.len() - suffix - prefix,
+            removed: old.len() - suffix - prefix,
+        }
+    }
+
+    /// `count` lines from `start` rewritten in place
+    fn rewritten(start: usize, count: usize) -> Self {
+        LineRange {
+            start,
+            end: start + count,
+            inserted: count,
+            removed: count,
+        }
+    }
+}
+
+/// A search hit, positioned in the same units as `EditDelta`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchMatch {
+    pub line: usize,
+    pub col: usize,
+    pub length: usize,
+}
+
+/// A line/col position in the same units as `EditDelta`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Where the caret lands after an edit, as a position and a char offset
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Caret {
+    pub line: usize,
+    pub col: usize,
+    pub offset: usize,
+}
+
+/// The lines an edit touched and where it left the caret
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct EditResult {
+    pub lines: LineRange,
+    pub caret: Caret,
+}
+
+/// One step of a quill-style text delta: `{ retain }`, `{ insert }` or
+/// `{ delete }`, walking the text from its start
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeltaOp {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+/// Columns `[start_col, end_col)` of a span within one line
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ColumnRange {
+    pub start_col: usize,
+    pub end_col: usize,
+}
+
+/// Where a `paste` left the caret and which lines it touched
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PasteResult {
+    pub caret: Position,
+    pub start_line: usize,
+    /// Inclusive
+    pub end_line: usize,
+}
+
+/// A collaborator's ephemeral presence state
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerState {
+    pub peer: String,
+    pub state: LoroValue,
+}
+
+/// A collaborator's selection, resolved against the local document
+///
+/// A collapsed selection is a plain caret, with `anchor_pos == head_pos`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerSelection {
+    pub peer: String,
+    pub anchor_pos: Position,
+    pub head_pos: Position,
+}
+
+/// A review comment on a text range, positioned as of now
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub id: String,
+    pub text: String,
+    pub resolved: bool,
+    pub anchor_pos: Position,
+    pub head_pos: Position,
+}
+
+/// Last peer to touch a line, as reported by `blame`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LineBlame {
+    pub line: usize,
+    pub peer: String,
+    /// Unix time in seconds
+    pub timestamp: i64,
+}
+
+/// A line holding concurrent insertions by several peers, which merged
+/// without conflict but may not read as either peer intended
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interleaving {
+    pub line: usize,
+    /// Sorted
+    pub peers: Vec<String>,
+}
+
+/// Size of the document's history
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OplogStats {
+    pub ops: usize,
+    pub change_count: usize,
+    /// Length of a full-history update export
+    pub estimated_bytes: usize,
+    pub frontier_count: usize,
+}
+
+impl OplogStats {
+    /// Measure the oplog of `doc`
+    pub fn of(doc: &LoroDoc) -> Self {
+        OplogStats {
+            ops: doc.len_ops(),
+            change_count: doc.len_changes(),
+            estimated_bytes: doc
+                .export(ExportMode::all_updates())
+                .map_or(0, |bytes| bytes.len()),
+            frontier_count: doc.oplog_frontiers().len(),
+        }
+    }
+}
+
+/// How much of an imported blob was applied, in ops
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportReport {
+    pub applied_count: usize,
+    /// Ops held back until their causal predecessors arrive
+    pub pending_count: usize,
+    /// Whether nothing is left pending
+    pub success: bool,
+}
+
+/// Size and cost of an import, as measured by `import_timed`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportMetrics {
+    /// Length of the imported blob
+    pub bytes: usize,
+    pub applied_ops: usize,
+    /// Wall-clock time of the whole import, including view bookkeeping
+    pub duration_ms: f64,
+}
+
+/// Ops of one peer that imported updates depend on but that haven't arrived
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingDependency {
+    pub peer: String,
+    /// Counter range `[start, end)` of the missing ops
+    pub start: i32,
+    pub end: i32,
+}
+
+/// Outcome of importing an update into a scratch copy of the document
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportPreview {
+    pub text: String,
+    pub changed_lines: Vec<usize>,
+}
+
+/// One op ID of a frontier, with the peer as a decimal string since it
+/// doesn't fit a JS number
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FrontierId {
+    pub peer: String,
+    pub counter: i32,
+}
+
+/// One op from the oplog, decoded for debugging sync issues
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OpRecord {
+    pub peer: String,
+    pub counter: i32,
+    pub container: String,
+    /// `insert`, `delete`, `mark`, `mark_end`, `set`, `remove` or `other`
+    pub kind: String,
+    /// Start of the affected text, in Unicode scalar values
+    pub pos: Option<usize>,
+    /// Inserted text, mark key or map key
+    pub text: Option<String>,
+    /// Characters removed by a `delete`
+    pub len: Option<usize>,
+}
+
+/// When edits are committed to the oplog
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CommitMode {
+    /// Every edit is its own commit
+    Immediate,
+    /// Every `ops` edits are coalesced into one commit
+    Debounced { ops: usize },
+}
+
+/// Size caps enforced on edits and imports, e.g. for sandboxed embeddings
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Longest the text may grow, in Unicode scalar values
+    #[serde(default)]
+    pub max_chars: Option<usize>,
+    /// Most ops the oplog may hold
+    #[serde(default)]
+    pub max_ops: Option<usize>,
+}
+
+/// One entry of the change history
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChangeRecord {
+    /// Commit message, or the author of an attributed edit
+    pub message: Option<String>,
+    pub peer: String,
+    /// Unix time in seconds
+    pub timestamp: i64,
+}
+
+/// Summary stored ahead of the document by `export_snapshot_with_meta`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotMeta {
+    /// First non-empty line, without Markdown heading markers
+    pub title: String,
+    /// Unicode scalar values
+    pub char_count: usize,
+    /// Unix time in seconds of the latest change, 0 if untracked
+    pub updated_at: i64,
+    /// Every peer that contributed to the history, sorted
+    pub peers: Vec<String>,
+}
+
+/// A run of lines forming one Markdown block, as returned by `paragraphs`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Paragraph {
+    pub start_line: usize,
+    /// Inclusive
+    pub end_line: usize,
+    pub text: String,
+}
+
+/// Document statistics for the status bar
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TextStats {
+    /// Counted like `get_line_count`, so a trailing newline starts a line
+    pub lines: usize,
+    /// Runs of non-whitespace, split on Unicode whitespace
+    pub words: usize,
+    /// Unicode scalar values
+    pub chars: usize,
+    /// UTF-8 bytes
+    pub bytes: usize,
+}
+
+impl TextStats {
+    /// Compute statistics over `content`
+    pub fn of(content: &str) -> Self {
+        TextStats {
+            lines: content.split('\n').count(),
+            words: content.split_whitespace().count(),
+            chars: content.chars().count(),
+            bytes: content.len(),
+        }
+    }
+}
+
+/// How a line differs between two versions
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A changed line; `line` indexes the old text for removals, else the new
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LineChange {
+    pub line: usize,
+    pub kind: LineChangeKind,
 }
 
 /// The main Kern Engine holding the Loro CRDT document
-#[wasm_bindgen]
-pub struct KernEngine {
+///
+/// This is the plain Rust API, usable natively (e.g. by a sync or persistence
+/// server); `KernEngine` wraps it for JS.
+pub struct KernCore {
     doc: LoroDoc,
+    undo: UndoManager,
     version: u64,
+    /// Oplog version at the last `export_updates` call
+    last_export: VersionVector,
+    /// Keeps the change subscription alive; dropping it unsubscribes
+    subscription: Option<Subscription>,
+    /// Like `subscription`, for `subscribe_deltas`
+    delta_subscription: Option<Subscription>,
+    /// Ephemeral presence of collaborators, keyed by peer ID
+    awareness: EphemeralStore,
+    /// Called once after each import of new ops with the new version and
+    /// changed lines
+    sync_callback: Option<SyncCallback>,
+    /// Set between `begin_batch` and `end_batch`; edits skip their commit
+    batching: bool,
+    commit_mode: CommitMode,
+    limits: Limits,
+    /// Edits held back by debounced mode since the last commit
+    held: usize,
+    /// Lines of `container` as of `ViewCache::version`
+    view_cache: RefCell<Option<ViewCache>>,
+    /// Recently served full views, oldest first, for `view_patch` to diff
+    served_views: RefCell<VecDeque<(u64, Vec<LineView>)>>,
+    /// Text container the single-container API operates on
+    container: String,
+    /// Whether `container` takes marks, see `with_container`
+    rich: bool,
+    /// Stored commit count, see `revision_count`
+    revisions: u64,
+    /// Lowest counter of each peer's imported ops that Loro still buffers
+    awaiting: BTreeMap<u64, i32>,
+    /// Whether `undo` only reverts this peer's edits
+    undo_local_only: bool,
+    /// Set by `set_readonly`; local edits fail but imports still apply
+    readonly: bool,
+    /// Encoded size of local commits since `mark_saved`
+    unsaved_bytes: Arc<AtomicUsize>,
+    /// Keeps `unsaved_bytes` counting; dropping it stops
+    _save_tracking: Subscription,
+    /// Position in the shared history while undoing everything
+    global_undo: Option<GlobalUndo>,
+}
+
+/// Progress of an "undo everything" session through the shared history
+struct GlobalUndo {
+    /// Frontier after each causally ordered prefix of the history
+    steps: Vec<Frontiers>,
+    /// Index into `steps` of the state currently shown
+    position: usize,
+    /// Oplog heads right after the last step; any other edit ends the session
+    heads: Frontiers,
 }
 
+/// JS binding for `KernCore`, converting arguments and results to `JsValue`
 #[wasm_bindgen]
-impl KernEngine {
-    /// Create a new KernEngine with an empty document
-    #[wasm_bindgen(constructor)]
-    pub fn new() -> Self {
-        let doc = LoroDoc::new();
+pub struct KernEngine {
+    core: KernCore,
+}
 
-        // Initialize with a text container for the document content
-        let text = doc.get_text("content");
-        text.insert(0, "# Welcome to Kern\n\nStart typing...").unwrap();
+impl From<KernCore> for KernEngine {
+    fn from(core: KernCore) -> Self {
+        KernEngine { core }
+    }
+}
 
-        KernEngine { doc, version: 0 }
+impl KernEngine {
+    /// The wrapped engine, for Rust code sharing a document with JS
+    pub fn core(&self) -> &KernCore {
+        &self.core
     }
 
-    /// Apply an edit delta from the JS side
-    #[wasm_bindgen]
-    pub fn apply_edit(&mut self, delta: JsValue) -> Result<JsValue, JsValue> {
-        let edit: EditDelta = serde_wasm_bindgen::from_value(delta)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    /// Mutable access to the wrapped engine
+    pub fn core_mut(&mut self) -> &mut KernCore {
+        &mut self.core
+    }
+}
 
-        let text = self.doc.get_text("content");
-        let content = text.to_string();
+/// Built view lines, valid while the engine's version is unchanged
+struct ViewCache {
+    version: u64,
+    lines: Vec<LineView>,
+}
 
-        // Calculate position from line/col
-        let mut pos = 0;
-        for (i, line) in content.lines().enumerate() {
-            if i == edit.line {
-                pos += edit.col.min(line.len());
-                break;
-            }
-            pos += line.len() + 1; // +1 for newline
-        }
+type SyncCallback = Box<dyn Fn(u64, &[usize])>;
 
-        // Apply the edit
-        if let Some(delete_count) = edit.delete {
-            if delete_count > 0 && pos > 0 {
-                text.delete(pos.saturating_sub(delete_count), delete_count).unwrap();
-            }
-        }
+type LineCallback = Box<dyn Fn(&[usize]) + Send + Sync>;
 
-        if let Some(insert_text) = &edit.insert {
-            text.insert(pos, insert_text).unwrap();
-        }
+/// A deferred delivery of coalesced changes, see `subscribe_lines_batched`
+pub type FlushTask = Box<dyn FnOnce() + Send>;
 
-        self.version += 1;
+/// Changes coalesced by `subscribe_lines_batched` awaiting delivery
+struct LineBatch {
+    text: LoroText,
+    /// Text at the last delivery, ops since, and whether a flush is scheduled
+    state: Mutex<(String, usize, bool)>,
+    callback: LineCallback,
+}
 
-        // Return affected line indices for efficient re-render
-        Ok(serde_wasm_bindgen::to_value(&vec![edit.line]).unwrap())
+impl LineBatch {
+    /// Deliver the lines changed since the last delivery, if any
+    fn flush(&self) {
+        let current = self.text.to_string();
+        let lines = {
+            let mut state = self.state.lock().unwrap();
+            let lines = changed_lines(&state.0, &current);
+            *state = (current, 0, false);
+            lines
+        };
+        if !lines.is_empty() {
+            (self.callback)(&lines);
+        }
     }
+}
 
-    /// Get the current document view
-    #[wasm_bindgen]
-    pub fn get_view(&self) -> JsValue {
-        let text = self.doc.get_text("content");
-        let content = text.to_string();
+/// Characters inserted or deleted across the text diffs of `event`
+fn event_ops(event: &DiffEvent) -> usize {
+    event
+        .events
+        .iter()
+        .filter_map(|container| match &container.diff {
+            Diff::Text(deltas) => Some(deltas),
+            _ => None,
+        })
+        .flatten()
+        .map(|delta| match delta {
+            TextDelta::Insert { insert, .. } => insert.chars().count(),
+            TextDelta::Delete { delete } => *delete,
+            TextDelta::Retain { .. } => 0,
+        })
+        .sum()
+}
 
-        let lines: Vec<LineView> = content
-            .lines()
-            .enumerate()
-            .map(|(i, line)| LineView {
-                id: i.to_string(),
-                content: line.to_string(),
-            })
-            .collect();
+/// A JS callback that can be captured by Loro's `Send + Sync` subscribers
+///
+/// WASM is single-threaded, so the function never actually crosses threads.
+struct JsCallback(js_sys::Function);
 
-        let view = DocumentView {
-            lines,
-            version: self.version,
-        };
+unsafe impl Send for JsCallback {}
+unsafe impl Sync for JsCallback {}
+
+impl JsCallback {
+    /// Call the function with `args` in a microtask
+    ///
+    /// Notifications fire inside `&mut self` engine methods, so a synchronous
+    /// call would fail with wasm-bindgen's "recursive use of an object" error
+    /// as soon as the callback read from the engine. Deferred, it runs once
+    /// the method has returned; microtasks keep the calls in order.
+    fn call_later(&self, args: js_sys::Array) {
+        let function = self.0.clone();
+        queue_microtask(move || {
+            // Errors thrown by the callback are the caller's concern, not ours
+            let _ = function.apply(&JsValue::NULL, &args);
+        });
+    }
+}
 
-        serde_wasm_bindgen::to_value(&view).unwrap()
+/// Run `task` in a microtask, after the current JS call returns
+fn queue_microtask(task: impl FnOnce() + 'static) {
+    // Frees itself once called, unlike a forgotten `Closure`; `Promise::then`
+    // only takes the latter, so call it by name
+    let task = Closure::once_into_js(move |_: JsValue| task());
+    let promise = js_sys::Promise::resolve(&JsValue::UNDEFINED);
+    let then = js_sys::Reflect::get(&promise, &"then".into())
+        .and_then(|then| then.dyn_into::<js_sys::Function>());
+    if let Ok(then) = then {
+        let _ = then.call1(&promise, &task);
     }
+}
 
-    /// Export full snapshot for periodic saves
+#[wasm_bindgen]
+impl KernEngine {
+    /// Create a new KernEngine seeded with the welcome text
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        KernCore::new().into()
+    }
+
+    /// Create a new KernEngine seeded with `initial`, which may be empty
     #[wasm_bindgen]
-    pub fn export_snapshot(&self) -> Vec<u8> {
-        self.doc.export(loro::ExportMode::Snapshot).unwrap()
+    pub fn with_content(initial: &str) -> KernEngine {
+        KernCore::with_content(initial).into()
     }
 
-    /// Export only updates since last export (lightweight)
+    /// Create an empty KernEngine whose text lives in the container `name`
     #[wasm_bindgen]
-    pub fn export_updates(&self) -> Vec<u8> {
-        // For now, export full snapshot - can optimize with Loro's update tracking
-        self.doc.export(loro::ExportMode::Snapshot).unwrap()
+    pub fn with_container_name(name: &str) -> KernEngine {
+        KernCore::with_container_name(name).into()
     }
 
-    /// Load document from saved bytes
+    /// Like `with_container_name`; unless `rich`, marks are refused with
+    /// `PLAIN_CONTAINER`, e.g. for a title or filename field
     #[wasm_bindgen]
-    pub fn load_from_bytes(&mut self, data: &[u8]) -> Result<(), JsValue> {
-        self.doc.import(data)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        self.version += 1;
-        Ok(())
+    pub fn with_container(name: &str, rich: bool) -> KernEngine {
+        KernCore::with_container(name, rich).into()
     }
 
-    /// Get current version number
+    /// Independent copy of this document under a fresh peer ID
     #[wasm_bindgen]
-    pub fn get_version(&self) -> u64 {
-        self.version
+    pub fn fork(&self) -> KernEngine {
+        self.core.fork().into()
     }
 
-    /// Get document content as plain text
+    /// Apply an edit delta from the JS side, optionally attributed to `author`
+    ///
+    /// Returns `{ lines, caret }`: the dirty line range for re-rendering and
+    /// the caret's `{ line, col, offset }` just after the inserted text.
     #[wasm_bindgen]
-    pub fn get_text(&self) -> String {
-        self.doc.get_text("content").to_string()
+    pub fn apply_edit(
+        &mut self,
+        delta: JsValue,
+        author: Option<String>,
+    ) -> Result<JsValue, KernError> {
+        let edit: EditDelta = decode_delta(delta)?;
+        let result = self.core.apply_delta_with_caret(&edit, author.as_deref())?;
+        Ok(serde_wasm_bindgen::to_value(&result)?)
     }
 
-    /// Set entire document content (for initial load)
+    /// Apply an array of edit deltas atomically as a single commit
     #[wasm_bindgen]
-    pub fn set_text(&mut self, content: &str) {
-        let text = self.doc.get_text("content");
-        let current = text.to_string();
-        if !current.is_empty() {
-            text.delete(0, current.len()).unwrap();
-        }
-        text.insert(0, content).unwrap();
-        self.version += 1;
+    pub fn apply_edits(
+        &mut self,
+        deltas: JsValue,
+        author: Option<String>,
+    ) -> Result<JsValue, KernError> {
+        let edits: Vec<EditDelta> = decode_delta(deltas)?;
+        let affected = self.core.apply_deltas_as(&edits, author.as_deref())?;
+        Ok(serde_wasm_bindgen::to_value(&affected)?)
     }
-}
 
-impl Default for KernEngine {
-    fn default() -> Self {
-        Self::new()
+    /// Apply an edit delta to a named text container, creating it if needed
+    #[wasm_bindgen]
+    pub fn apply_edit_to(&mut self, container: &str, delta: JsValue) -> Result<JsValue, KernError> {
+        let edit: EditDelta = decode_delta(delta)?;
+        let affected = self.core.apply_delta_to(container, &edit)?;
+        Ok(serde_wasm_bindgen::to_value(&affected)?)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Insert possibly multi-line `text` at the caret as one edit, returning
+    /// `{ caret, start_line, end_line }`
+    #[wasm_bindgen]
+    pub fn paste(&mut self, line: usize, col: usize, text: &str) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.paste(line, col, text)?,
+        )?)
+    }
+
+    /// Delete from the caret to the previous or next word boundary, as for
+    /// Ctrl+Backspace and Ctrl+Delete
+    #[wasm_bindgen]
+    pub fn delete_word(
+        &mut self,
+        line: usize,
+        col: usize,
+        forward: bool,
+    ) -> Result<JsValue, KernError> {
+        let affected = self.core.delete_word(line, col, forward)?;
+        Ok(serde_wasm_bindgen::to_value(&affected)?)
+    }
+
+    /// Columns `{ start_col, end_col }` of the word under the caret, for
+    /// double-click selection
+    #[wasm_bindgen]
+    pub fn word_range_at(&self, line: usize, col: usize) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.word_range_at(line, col)?,
+        )?)
+    }
+
+    /// Columns `{ start_col, end_col }` spanning all of `line`, for
+    /// triple-click selection
+    #[wasm_bindgen]
+    pub fn line_range_at(&self, line: usize) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.line_range_at(line)?,
+        )?)
+    }
+
+    /// Hold subsequent edits pending until `end_batch` or `commit`, so they
+    /// land as a single commit and undo step
+    #[wasm_bindgen]
+    pub fn begin_batch(&mut self) {
+        self.core.begin_batch()
+    }
+
+    /// Leave batch mode and commit everything held since `begin_batch`
+    #[wasm_bindgen]
+    pub fn end_batch(&mut self) {
+        self.core.end_batch()
+    }
+
+    /// Flush Loro's pending transaction
+    #[wasm_bindgen]
+    pub fn commit(&mut self) {
+        self.core.commit()
+    }
+
+    /// Choose between `{ type: "immediate" }`, committing every edit, and
+    /// `{ type: "debounced", ops: n }`, coalescing every `n` edits into one
+    /// commit and undo step
+    #[wasm_bindgen]
+    pub fn set_commit_mode(&mut self, mode: JsValue) -> Result<(), KernError> {
+        self.core.set_mode(serde_wasm_bindgen::from_value(mode)?);
+        Ok(())
+    }
+
+    /// Cap the document at `{ max_chars, max_ops }`; either may be omitted
+    ///
+    /// Edits and imports that would exceed a cap fail with `LIMIT_EXCEEDED`
+    /// and leave the document untouched. Undo and redo are exempt.
+    #[wasm_bindgen]
+    pub fn set_limits(&mut self, limits: JsValue) -> Result<(), KernError> {
+        self.core
+            .set_limits(serde_wasm_bindgen::from_value(limits)?);
+        Ok(())
+    }
+
+    /// Commit any edits buffered by debounced mode
+    #[wasm_bindgen]
+    pub fn flush(&mut self) {
+        self.core.flush()
+    }
+
+    /// Whether edits are waiting in an uncommitted transaction
+    #[wasm_bindgen]
+    pub fn has_uncommitted_changes(&self) -> bool {
+        self.core.has_uncommitted_changes()
+    }
+
+    /// Flush pending ops as one commit carrying `message`
+    #[wasm_bindgen]
+    pub fn commit_with_message(&mut self, message: &str) {
+        self.core.commit_with_message(message)
+    }
+
+    /// Recent commits as `{ message, peer, timestamp }`, newest first
+    #[wasm_bindgen]
+    pub fn change_history(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.history())?)
+    }
+
+    /// Per line, the peer and timestamp of the latest change to it
+    #[wasm_bindgen]
+    pub fn blame(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.blame_lines())?)
+    }
+
+    /// Lines where peers inserted text concurrently since encoded frontier
+    /// `since`, as `[{ line, peers }]`, for flagging merges for review
+    #[wasm_bindgen]
+    pub fn find_interleavings(&self, since: &[u8]) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.find_interleavings(since)?,
+        )?)
+    }
+
+    /// Get the current document view
+    ///
+    /// With `wrap_width`, each line also carries the `visual_rows` it fills
+    /// when word-wrapped at that many columns.
+    #[wasm_bindgen]
+    pub fn get_view(&self, wrap_width: Option<usize>) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.view().wrapped(wrap_width),
+        )?)
+    }
+
+    /// Line operations taking a view cached at `since_version` to the current
+    /// one, as `{ version, full, ops, lines }`
+    ///
+    /// `full` is set, with the whole view in `lines`, when `since_version`
+    /// is too old to diff against.
+    #[wasm_bindgen]
+    pub fn get_view_patch(&self, since_version: u64) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.view_patch(since_version),
+        )?)
+    }
+
+    /// Get the view of lines `[start, end)` only, clamped to the document,
+    /// optionally with `visual_rows` at `wrap_width` as for `get_view`
+    #[wasm_bindgen]
+    pub fn get_view_range(
+        &self,
+        start: usize,
+        end: usize,
+        wrap_width: Option<usize>,
+    ) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.view_range(start, end).wrapped(wrap_width),
+        )?)
+    }
+
+    /// Find all non-overlapping matches of `query` as `{ line, col, length }`
+    #[wasm_bindgen]
+    pub fn search(&self, query: &str, case_sensitive: bool) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.find(query, case_sensitive),
+        )?)
+    }
+
+    /// Encode a stable cursor anchored at `line`/`col` that survives remote edits
+    #[wasm_bindgen]
+    pub fn cursor_from_pos(&self, line: usize, col: usize) -> Result<Vec<u8>, KernError> {
+        self.core.cursor_from_pos(line, col)
+    }
+
+    /// Resolve an encoded cursor to its current `{ line, col }`
+    #[wasm_bindgen]
+    pub fn pos_from_cursor(&self, cursor: &[u8]) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.resolve_cursor(cursor)?,
+        )?)
+    }
+
+    /// Get `{ lines, words, chars, bytes }` for the document
+    ///
+    /// This is a linear scan of the content; debounce it for large documents.
+    #[wasm_bindgen]
+    pub fn stats(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.stats())?)
+    }
+
+    /// Get `{ lines, words, chars, bytes }` for one named text container
+    #[wasm_bindgen]
+    pub fn stats_of(&self, container: &str) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.stats_of(container)?,
+        )?)
+    }
+
+    /// Oplog size as `{ ops, change_count, estimated_bytes, frontier_count }`
+    #[wasm_bindgen]
+    pub fn oplog_stats(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.oplog_stats())?)
+    }
+
+    /// Whether history has grown large enough relative to the content that
+    /// exporting a shallow snapshot is worthwhile
+    #[wasm_bindgen]
+    pub fn should_compact(&self) -> bool {
+        self.core.should_compact()
+    }
+
+    /// Drop all history in place, keeping the current content, marks and
+    /// metadata under a fresh peer ID
+    ///
+    /// Undo history, checkpoints and time-travel are lost. Change
+    /// subscriptions end; subscribe again afterwards.
+    #[wasm_bindgen]
+    pub fn compact(&mut self) -> Result<(), KernError> {
+        self.core.compact()
+    }
+
+    /// Replace every match of `query` in one commit, returning the count
+    ///
+    /// Matches are replaced back to front so earlier offsets stay valid, and
+    /// the single commit makes the whole replacement one undo step.
+    #[wasm_bindgen]
+    pub fn replace_all(
+        &mut self,
+        query: &str,
+        replacement: &str,
+        case_sensitive: bool,
+    ) -> Result<usize, KernError> {
+        self.core.replace_all(query, replacement, case_sensitive)
+    }
+
+    /// Replace the first match at or after `from_line`/`from_col`, returning
+    /// the `{ line, col }` just past the replacement, or null if none matched
+    #[wasm_bindgen]
+    pub fn replace_next(
+        &mut self,
+        query: &str,
+        replacement: &str,
+        from_line: usize,
+        from_col: usize,
+        case_sensitive: bool,
+        wrap: bool,
+    ) -> Result<JsValue, KernError> {
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+        Ok(self
+            .core
+            .replace_next(
+                query,
+                replacement,
+                Position {
+                    line: from_line,
+                    col: from_col,
+                },
+                case_sensitive,
+                wrap,
+            )?
+            .serialize(&serializer)?)
+    }
+
+    /// Insert `text` at an absolute character offset
+    #[wasm_bindgen]
+    pub fn insert_at(&mut self, offset: usize, text: &str) -> Result<(), KernError> {
+        self.core.insert_at(offset, text)
+    }
+
+    /// Delete `count` characters starting at an absolute character offset
+    #[wasm_bindgen]
+    pub fn delete_at(&mut self, offset: usize, count: usize) -> Result<(), KernError> {
+        self.core.delete_at(offset, count)
+    }
+
+    /// Character offset of a line/col position, with `col` clamped to the line
+    ///
+    /// With `graphemes`, `col` counts grapheme clusters instead of characters.
+    #[wasm_bindgen]
+    pub fn offset_of(
+        &self,
+        line: usize,
+        col: usize,
+        graphemes: Option<bool>,
+    ) -> Result<usize, KernError> {
+        if graphemes.unwrap_or(false) {
+            self.core.grapheme_offset_of(line, col)
+        } else {
+            self.core.offset_of(line, col)
+        }
+    }
+
+    /// Line/col position of a character offset as `{ line, col }`
+    ///
+    /// With `graphemes`, `col` counts grapheme clusters instead of characters.
+    #[wasm_bindgen]
+    pub fn line_col_of(
+        &self,
+        offset: usize,
+        graphemes: Option<bool>,
+    ) -> Result<JsValue, KernError> {
+        let position = if graphemes.unwrap_or(false) {
+            self.core.grapheme_position_of(offset)?
+        } else {
+            self.core.position_of(offset)?
+        };
+        Ok(serde_wasm_bindgen::to_value(&position)?)
+    }
+
+    /// Number of grapheme clusters on `line`, i.e. the caret stops in it
+    #[wasm_bindgen]
+    pub fn grapheme_len(&self, line: usize) -> Result<usize, KernError> {
+        self.core.grapheme_len(line)
+    }
+
+    /// Line-ending style of the stored content: `"lf"`, `"crlf"` or `"mixed"`
+    #[wasm_bindgen]
+    pub fn detect_line_ending(&self) -> String {
+        self.core.detect_line_ending()
+    }
+
+    /// Rewrite every line ending to `to` (`"lf"` or `"crlf"`) in place
+    ///
+    /// Lines are always split on LF, so a CR before it shows up as part of
+    /// the line's content; CRLF is meant for embeddings that hand the text to
+    /// CRLF tooling. Only local input such as `set_text` and pastes is
+    /// normalized to LF; imports apply as sent, so a conversion survives sync.
+    #[wasm_bindgen]
+    pub fn convert_line_endings(&mut self, to: &str) -> Result<(), KernError> {
+        self.core.convert_line_endings(to)
+    }
+
+    /// Delete the selection between two line/col positions in either order,
+    /// returning the `{ start, end, inserted, removed }` line range touched
+    #[wasm_bindgen]
+    pub fn delete_range(
+        &mut self,
+        start_line: usize,
+        start_col: usize,
+        end_line: usize,
+        end_col: usize,
+    ) -> Result<JsValue, KernError> {
+        let affected = self.core.delete_between(
+            Position {
+                line: start_line,
+                col: start_col,
+            },
+            Position {
+                line: end_line,
+                col: end_col,
+            },
+        )?;
+        Ok(serde_wasm_bindgen::to_value(&affected)?)
+    }
+
+    /// Move line `from` so it ends up at index `to`, returning the lines
+    /// between the two
+    #[wasm_bindgen]
+    pub fn move_line(&mut self, from: usize, to: usize) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.move_line(from, to)?,
+        )?)
+    }
+
+    /// Apply a formatting mark (e.g. "bold") over the character range `[start, end)`
+    #[wasm_bindgen]
+    pub fn mark(
+        &mut self,
+        start: usize,
+        end: usize,
+        key: &str,
+        value: JsValue,
+    ) -> Result<(), KernError> {
+        let value: LoroValue = serde_wasm_bindgen::from_value(value)?;
+        self.core.mark_range(start, end, key, value)
+    }
+
+    /// Remove a formatting mark from the character range `[start, end)`
+    #[wasm_bindgen]
+    pub fn unmark(&mut self, start: usize, end: usize, key: &str) -> Result<(), KernError> {
+        self.core.unmark(start, end, key)
+    }
+
+    /// Set the block type of a line, which follows the line through edits
+    /// and merges
+    ///
+    /// Blocks are keyed by line ID; a line broken at its end keeps its type,
+    /// leaving the new line after it a paragraph.
+    #[wasm_bindgen]
+    pub fn set_block_type(&mut self, line: usize, kind: &str) -> Result<(), KernError> {
+        self.core.set_block_type(line, kind)
+    }
+
+    /// Formatting active at a caret position, as an object of mark values
+    #[wasm_bindgen]
+    pub fn marks_at(&self, line: usize, col: usize) -> Result<JsValue, KernError> {
+        // A plain object rather than serde-wasm-bindgen's default `Map`
+        let serializer = serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true);
+        Ok(self
+            .core
+            .marks_at_caret(line, col)?
+            .serialize(&serializer)?)
+    }
+
+    /// Export full snapshot for periodic saves
+    #[wasm_bindgen]
+    pub fn export_snapshot(&self) -> Result<Vec<u8>, KernError> {
+        self.core.export_snapshot()
+    }
+
+    /// Export a snapshot headed by metadata that `read_snapshot_meta` can
+    /// read without loading the document
+    #[wasm_bindgen]
+    pub fn export_snapshot_with_meta(&self) -> Result<Vec<u8>, KernError> {
+        self.core.export_snapshot_with_meta()
+    }
+
+    /// Export a deflate-compressed snapshot for size-sensitive storage
+    #[wasm_bindgen]
+    pub fn export_snapshot_compressed(&self) -> Result<Vec<u8>, KernError> {
+        self.core.export_snapshot_compressed()
+    }
+
+    /// Export a full snapshot as standard base64, for text-only transports
+    #[wasm_bindgen]
+    pub fn export_snapshot_base64(&self) -> Result<String, KernError> {
+        self.core.export_snapshot_base64()
+    }
+
+    /// Load a snapshot from `export_snapshot_base64`
+    #[wasm_bindgen]
+    pub fn load_from_base64(&mut self, data: &str) -> Result<(), KernError> {
+        self.core.import_base64(data).map(|_| ())
+    }
+
+    /// Load a blob from `export_snapshot_compressed`, or a raw snapshot
+    #[wasm_bindgen]
+    pub fn load_from_compressed(&mut self, data: &[u8]) -> Result<JsValue, KernError> {
+        let affected = self.core.import_compressed(data)?;
+        Ok(serde_wasm_bindgen::to_value(&affected)?)
+    }
+
+    /// Export a snapshot that keeps only the most recent `depth` ops of history
+    ///
+    /// The current state is preserved and recent edits stay mergeable, but
+    /// undo and time-travel past the trim point are lost for whoever loads it.
+    /// Shallow snapshots can only be loaded into an empty document.
+    #[wasm_bindgen]
+    pub fn export_shallow_snapshot(&self, depth: usize) -> Result<Vec<u8>, KernError> {
+        self.core.export_shallow_snapshot(depth)
+    }
+
+    /// Export the full history in a byte-stable form for content addressing
+    ///
+    /// `export_snapshot` also encodes Loro's materialized state, whose layout
+    /// depends on the order ops were imported in, so converged peers can
+    /// produce different bytes. The update encoding is ordered by op ID and
+    /// matches for any two peers holding the same history. Peers that reached
+    /// the same text through different histories, or a shallow snapshot,
+    /// still differ. Loads with `load_from_bytes`.
+    #[wasm_bindgen]
+    pub fn export_canonical(&self) -> Result<Vec<u8>, KernError> {
+        self.core.export_canonical()
+    }
+
+    /// Export only the current text, marks and block types, without history
+    ///
+    /// The result is a brand-new document under a throwaway peer, so deleted
+    /// text, authors and timestamps don't leak to whoever receives it. It
+    /// shares no history with this one and can't be merged back into it.
+    #[wasm_bindgen]
+    pub fn export_redacted_snapshot(&self) -> Result<Vec<u8>, KernError> {
+        self.core.export_redacted_snapshot()
+    }
+
+    /// The content as `{ lines: [{ content, marks?, block }], version }`
+    ///
+    /// A human-readable alternative to snapshots that drops all history.
+    #[wasm_bindgen]
+    pub fn to_json(&self) -> Result<JsValue, KernError> {
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+        Ok(self.core.to_json().serialize(&serializer)?)
+    }
+
+    /// Replace the content with a document from `to_json`, as one commit
+    #[wasm_bindgen]
+    pub fn from_json(&mut self, value: JsValue) -> Result<(), KernError> {
+        let json: DocumentJson = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| KernError::InvalidDocument(e.to_string()))?;
+        self.core.from_json(&json)
+    }
+
+    /// Export only updates since last export (lightweight)
+    #[wasm_bindgen]
+    pub fn export_updates(&mut self) -> Result<Vec<u8>, KernError> {
+        self.core.export_updates()
+    }
+
+    /// Forget the last export so the next `export_updates` ships full history
+    #[wasm_bindgen]
+    pub fn reset_update_cursor(&mut self) {
+        self.core.reset_update_cursor()
+    }
+
+    /// Whether at least `threshold_bytes` of local edits have been committed
+    /// since `mark_saved`
+    #[wasm_bindgen]
+    pub fn autosave_due(&self, threshold_bytes: usize) -> bool {
+        self.core.autosave_due(threshold_bytes)
+    }
+
+    /// Reset the `autosave_due` counter after a successful persist
+    #[wasm_bindgen]
+    pub fn mark_saved(&mut self) {
+        self.core.mark_saved()
+    }
+
+    /// Encode the oplog version vector for sync negotiation
+    #[wasm_bindgen]
+    pub fn get_version_vector(&self) -> Vec<u8> {
+        self.core.get_version_vector()
+    }
+
+    /// Export the updates a peer is missing, given its encoded version vector
+    #[wasm_bindgen]
+    pub fn export_from_version_vector(&self, vv: &[u8]) -> Result<Vec<u8>, KernError> {
+        self.core.export_from_version_vector(vv)
+    }
+
+    /// Encode the frontier of the currently visible state
+    #[wasm_bindgen]
+    pub fn current_frontier(&self) -> Vec<u8> {
+        self.core.current_frontier()
+    }
+
+    /// The current frontier as `[{ peer, counter }]`
+    #[wasm_bindgen]
+    pub fn frontier_json(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.frontier_ids())?)
+    }
+
+    /// Causal order of two `frontier_json` values: -1 if `a` happened before
+    /// `b`, 0 if equal, 1 if after, and `undefined` if they are concurrent
+    #[wasm_bindgen]
+    pub fn compare_frontiers(&self, a: JsValue, b: JsValue) -> Result<Option<i32>, KernError> {
+        let a: Vec<FrontierId> = serde_wasm_bindgen::from_value(a)?;
+        let b: Vec<FrontierId> = serde_wasm_bindgen::from_value(b)?;
+        Ok(self.core.compare(&a, &b)?.map(|order| order as i32))
+    }
+
+    /// Ops after encoded frontier `from` up to `to`, as `{ peer, counter,
+    /// container, kind, pos, text, len }` in causal order (diagnostics only)
+    #[wasm_bindgen]
+    pub fn ops_between(&self, from: &[u8], to: &[u8]) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.ops_between(from, to)?,
+        )?)
+    }
+
+    /// Check the document out to a past frontier (read-only until returning)
+    #[wasm_bindgen]
+    pub fn checkout(&mut self, frontier: &[u8]) -> Result<(), KernError> {
+        self.core.checkout(frontier)
+    }
+
+    /// Whether the document is checked out to a past version; edits fail
+    /// with `DETACHED` until `checkout_to_latest`
+    #[wasm_bindgen]
+    pub fn is_detached(&self) -> bool {
+        self.core.is_detached()
+    }
+
+    /// Lock or unlock local editing, e.g. for viewer embeddings
+    ///
+    /// While locked, edits fail with `READ_ONLY`; remote updates still load.
+    #[wasm_bindgen]
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.core.set_readonly(readonly)
+    }
+
+    /// Whether local editing is locked by `set_readonly`
+    #[wasm_bindgen]
+    pub fn is_readonly(&self) -> bool {
+        self.core.is_readonly()
+    }
+
+    /// Return from a past version to the live head
+    #[wasm_bindgen]
+    pub fn checkout_to_latest(&mut self) {
+        self.core.checkout_to_latest()
+    }
+
+    /// Bookmark the current version under `name` (synced with the document)
+    #[wasm_bindgen]
+    pub fn create_checkpoint(&mut self, name: &str) -> Result<(), KernError> {
+        self.core.create_checkpoint(name)
+    }
+
+    /// Names of all checkpoints, sorted
+    #[wasm_bindgen]
+    pub fn list_checkpoints(&self) -> Vec<String> {
+        self.core.list_checkpoints()
+    }
+
+    /// Check out the version saved under `name`; read-only until
+    /// `checkout_to_latest`
+    #[wasm_bindgen]
+    pub fn restore_checkpoint(&mut self, name: &str) -> Result<(), KernError> {
+        self.core.restore_checkpoint(name)
+    }
+
+    /// What restoring checkpoint `name` would change, as `[{ line, kind }]`
+    /// from the current text to the checkpoint's
+    #[wasm_bindgen]
+    pub fn diff_against_checkpoint(&self, name: &str) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.diff_against_checkpoint(name)?,
+        )?)
+    }
+
+    /// Load document from saved bytes, returning the
+    /// `{ start, end, inserted, removed }` range of lines that changed
+    #[wasm_bindgen]
+    pub fn load_from_bytes(&mut self, data: &[u8]) -> Result<JsValue, KernError> {
+        let affected = self.core.import_bytes(data)?;
+        Ok(serde_wasm_bindgen::to_value(&affected)?)
+    }
+
+    /// Load saved bytes like `load_from_bytes`, measuring the import as
+    /// `{ bytes, applied_ops, duration_ms }` for performance dashboards
+    #[wasm_bindgen]
+    pub fn import_timed(&mut self, data: &[u8]) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.import_timed(data)?,
+        )?)
+    }
+
+    /// Load updates, reporting `{ applied_count, pending_count, success }` so
+    /// callers can tell when ops are buffered awaiting a missing predecessor
+    #[wasm_bindgen]
+    pub fn import_with_status(&mut self, data: &[u8]) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.import_report(data)?,
+        )?)
+    }
+
+    /// Ops that buffered imports are waiting for, as `[{ peer, start, end }]`
+    /// counter ranges to request from the sender
+    #[wasm_bindgen]
+    pub fn pending_dependencies(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.pending_dependencies(),
+        )?)
+    }
+
+    /// Start a chunked import for payloads streamed in pieces
+    #[wasm_bindgen]
+    pub fn begin_import(&self) -> ImportSession {
+        self.core.begin_import()
+    }
+
+    /// `export_snapshot` split into `chunk_size`-byte pieces, as
+    /// `{ total_len, chunks: Uint8Array[] }` for streaming to storage
+    #[wasm_bindgen]
+    pub fn export_snapshot_chunked(&self, chunk_size: usize) -> Result<JsValue, KernError> {
+        let exported = self.core.export_snapshot_chunked(chunk_size)?;
+        let chunks: js_sys::Array = exported
+            .chunks
+            .iter()
+            .map(|chunk| js_sys::Uint8Array::from(chunk.as_slice()))
+            .collect();
+        let result = js_sys::Object::new();
+        let set = |key: &str, value: &JsValue| js_sys::Reflect::set(&result, &key.into(), value);
+        set("total_len", &(exported.total_len as f64).into())
+            .and_then(|_| set("chunks", &chunks))
+            .map_err(|e| KernError::Serialization(format!("{e:?}")))?;
+        Ok(result.into())
+    }
+
+    /// Show what importing `data` would do, as `{ text, changed_lines }`,
+    /// without applying it
+    #[wasm_bindgen]
+    pub fn preview_import(&self, data: &[u8]) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.preview(data)?)?)
+    }
+
+    /// Get current version number
+    #[wasm_bindgen]
+    pub fn get_version(&self) -> u64 {
+        self.core.get_version()
+    }
+
+    /// When the document was created, in Unix seconds, if recorded
+    #[wasm_bindgen]
+    pub fn created_at(&self) -> Option<f64> {
+        self.core.created_at().map(|secs| secs as f64)
+    }
+
+    /// Number of commits made to the document, persisted and merged
+    #[wasm_bindgen]
+    pub fn revision_count(&self) -> u64 {
+        self.core.revision_count()
+    }
+
+    /// Short hash of the visible text, marks and block types
+    #[wasm_bindgen]
+    pub fn content_hash(&self) -> Result<String, KernError> {
+        self.core.content_hash()
+    }
+
+    /// Get document content as plain text
+    #[wasm_bindgen]
+    pub fn get_text(&self) -> String {
+        self.core.get_text()
+    }
+
+    /// Serialize the document to Markdown, rendering formatting marks as
+    /// inline syntax (`**bold**`, `*italic*`, links, ...)
+    #[wasm_bindgen]
+    pub fn to_markdown(&self) -> String {
+        self.core.to_markdown()
+    }
+
+    /// Document content with all formatting stripped
+    #[wasm_bindgen]
+    pub fn to_plain_text(&self) -> String {
+        self.core.to_plain_text()
+    }
+
+    /// Blocks of consecutive non-blank lines as `{ start_line, end_line, text }`,
+    /// with fenced code blocks kept whole
+    #[wasm_bindgen]
+    pub fn paragraphs(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.paragraphs())?)
+    }
+
+    /// Get a single line's content, or `None` past the end of the document
+    #[wasm_bindgen]
+    pub fn get_line(&self, index: usize) -> Option<String> {
+        self.core.get_line(index)
+    }
+
+    /// Number of lines in the document
+    #[wasm_bindgen]
+    pub fn get_line_count(&self) -> usize {
+        self.core.get_line_count()
+    }
+
+    /// Get the content of a named text container as plain text
+    #[wasm_bindgen]
+    pub fn get_text_of(&self, container: &str) -> String {
+        self.core.get_text_of(container)
+    }
+
+    /// Names of all root text containers in the document
+    #[wasm_bindgen]
+    pub fn list_containers(&self) -> Vec<String> {
+        self.core.list_containers()
+    }
+
+    /// Every root container (texts, maps, lists) as one plain JS object
+    ///
+    /// A debugging aid for inspecting merged structure; it copies the whole
+    /// document, so it can be large and isn't for hot paths.
+    #[wasm_bindgen]
+    pub fn deep_value(&self) -> Result<JsValue, KernError> {
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+        Ok(self.core.deep_value().serialize(&serializer)?)
+    }
+
+    /// Empty the text container `name` with ordinary delete ops
+    #[wasm_bindgen]
+    pub fn clear_container(&mut self, name: &str) -> Result<(), KernError> {
+        self.core.clear_container(name)
+    }
+
+    /// Empty the text container `name` and hide it from this peer's deep
+    /// value
+    #[wasm_bindgen]
+    pub fn remove_container(&mut self, name: &str) -> Result<(), KernError> {
+        self.core.remove_container(name)
+    }
+
+    /// Set entire document content, merging with concurrent `set_text` calls
+    /// as described on `KernCore::set_text`
+    #[wasm_bindgen]
+    pub fn set_text(&mut self, content: &str) -> Result<(), KernError> {
+        self.core.set_text(content)
+    }
+
+    /// Replace the document content by applying only the minimal set of
+    /// inserts and deletes, so unchanged text keeps its CRDT identity
+    #[wasm_bindgen]
+    pub fn set_text_diffed(&mut self, content: &str) -> Result<(), KernError> {
+        self.core.set_text_diffed(content)
+    }
+
+    /// Replace the content with a full new text from a tool, e.g. a
+    /// formatter, as one minimal-diff commit and undo step
+    ///
+    /// Returns the changed hunks as `[{ start, end, inserted, removed }]`.
+    #[wasm_bindgen]
+    pub fn apply_external_text(&mut self, new_text: &str) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.apply_external_text(new_text)?,
+        )?)
+    }
+
+    /// Call `callback` with the changed line indices after every local or
+    /// remote change, replacing any previous subscription
+    ///
+    /// Like every engine callback, it runs in a microtask once the call that
+    /// made the change has returned, so it may read from the engine.
+    #[wasm_bindgen]
+    pub fn subscribe(&mut self, callback: js_sys::Function) {
+        let callback = JsCallback(callback);
+        self.core.subscribe_lines(move |lines| {
+            if let Ok(lines) = serde_wasm_bindgen::to_value(lines) {
+                callback.call_later(js_sys::Array::of1(&lines));
+            }
+        });
+    }
+
+    /// Like `subscribe`, but coalesce changes into one call carrying every
+    /// line that changed since the previous call
+    ///
+    /// The call comes once `max_lag_ops` ops have piled up, or in the next
+    /// microtask, whichever is first. Replaces any previous subscription.
+    #[wasm_bindgen]
+    pub fn subscribe_batched(&mut self, callback: js_sys::Function, max_lag_ops: usize) {
+        let callback = JsCallback(callback);
+        self.core.subscribe_lines_batched(
+            max_lag_ops,
+            move |lines| {
+                if let Ok(lines) = serde_wasm_bindgen::to_value(lines) {
+                    callback.call_later(js_sys::Array::of1(&lines));
+                }
+            },
+            queue_microtask,
+        );
+    }
+
+    /// Call `callback` with the character-level delta, as
+    /// `[{ retain } | { insert } | { delete }]`, after every local or remote
+    /// change, e.g. for incremental syntax highlighting
+    ///
+    /// Independent of `subscribe`; replaces any previous delta subscription.
+    #[wasm_bindgen]
+    pub fn subscribe_deltas(&mut self, callback: js_sys::Function) {
+        let callback = JsCallback(callback);
+        self.core.subscribe_deltas(move |delta| {
+            if let Ok(delta) = serde_wasm_bindgen::to_value(delta) {
+                callback.call_later(js_sys::Array::of1(&delta));
+            }
+        });
+    }
+
+    /// Stop delivering change notifications
+    #[wasm_bindgen]
+    pub fn unsubscribe(&mut self) {
+        self.core.unsubscribe()
+    }
+
+    /// Call `callback(version, changedLines)` exactly once after each import
+    /// that brought new ops
+    ///
+    /// Unlike `subscribe`, this fires per `load_from_bytes` call rather than
+    /// per change event, e.g. to clear a "syncing…" indicator. Redundant
+    /// imports leave the version alone and don't fire it. Like `subscribe`,
+    /// it runs in a microtask after the import returns.
+    #[wasm_bindgen]
+    pub fn on_sync(&mut self, callback: js_sys::Function) {
+        let callback = JsCallback(callback);
+        self.core.on_sync_with(move |version, lines| {
+            if let Ok(lines) = serde_wasm_bindgen::to_value(lines) {
+                callback.call_later(js_sys::Array::of2(&JsValue::from(version), &lines));
+            }
+        });
+    }
+
+    /// Get the Loro peer ID as a string (u64 doesn't fit a JS number)
+    #[wasm_bindgen]
+    pub fn get_peer_id(&self) -> String {
+        self.core.get_peer_id()
+    }
+
+    /// Set the Loro peer ID; must be called before any pending local ops
+    #[wasm_bindgen]
+    pub fn set_peer_id(&mut self, id: &str) -> Result<(), KernError> {
+        self.core.set_peer_id(id)
+    }
+
+    /// Set this peer's presence state (cursor, name, colour, ...)
+    #[wasm_bindgen]
+    pub fn set_local_awareness(&mut self, state: JsValue) -> Result<(), KernError> {
+        let state: LoroValue = serde_wasm_bindgen::from_value(state)?;
+        self.core.set_local_presence(state);
+        Ok(())
+    }
+
+    /// Share this peer's selection, given cursors from `cursor_from_pos`
+    #[wasm_bindgen]
+    pub fn set_local_selection(&mut self, anchor: &[u8], head: &[u8]) -> Result<(), KernError> {
+        self.core.set_local_selection(anchor, head)
+    }
+
+    /// Live selections of all peers as `{ peer, anchor_pos, head_pos }`
+    #[wasm_bindgen]
+    pub fn get_peer_selections(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.peer_selections())?)
+    }
+
+    /// Attach a comment to the range between two encoded cursors, returning
+    /// its ID
+    #[wasm_bindgen]
+    pub fn add_comment(
+        &mut self,
+        anchor: Vec<u8>,
+        head: Vec<u8>,
+        text: &str,
+    ) -> Result<String, KernError> {
+        self.core.add_comment(&anchor, &head, text)
+    }
+
+    /// All comments as `{ id, text, resolved, anchor_pos, head_pos }`, in
+    /// document order
+    #[wasm_bindgen]
+    pub fn list_comments(&self) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(&self.core.comments())?)
+    }
+
+    /// Mark a comment as resolved
+    #[wasm_bindgen]
+    pub fn resolve_comment(&mut self, id: &str) -> Result<(), KernError> {
+        self.core.resolve_comment(id)
+    }
+
+    /// Encode every known peer's presence for broadcasting
+    #[wasm_bindgen]
+    pub fn encode_awareness(&self) -> Vec<u8> {
+        self.core.encode_awareness()
+    }
+
+    /// Merge a remote awareness update and return all live `{ peer, state }`
+    #[wasm_bindgen]
+    pub fn apply_awareness(&mut self, data: &[u8]) -> Result<JsValue, KernError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.core.merge_awareness(data)?,
+        )?)
+    }
+
+    /// Undo the last local change, returning whether anything was undone
+    #[wasm_bindgen]
+    pub fn undo(&mut self) -> Result<bool, KernError> {
+        self.core.undo()
+    }
+
+    /// Redo the last undone change, returning whether anything was redone
+    #[wasm_bindgen]
+    pub fn redo(&mut self) -> Result<bool, KernError> {
+        self.core.redo()
+    }
+
+    /// Choose whether `undo` reverts only this peer's edits (the default)
+    /// or every change in the shared history, newest first
+    #[wasm_bindgen]
+    pub fn set_undo_scope(&mut self, local_only: bool) {
+        self.core.set_undo_scope(local_only)
+    }
+
+    /// Whether there is a change to undo
+    #[wasm_bindgen]
+    pub fn can_undo(&self) -> bool {
+        self.core.can_undo()
+    }
+
+    /// Whether there is a change to redo
+    #[wasm_bindgen]
+    pub fn can_redo(&self) -> bool {
+        self.core.can_redo()
+    }
+}
+
+impl KernCore {
+    /// Create a new KernCore seeded with the welcome text
+    pub fn new() -> Self {
+        Self::with_content(WELCOME_TEXT)
+    }
+
+    /// Create a new KernCore seeded with `initial`, which may be empty
+    pub fn with_content(initial: &str) -> Self {
+        Self::seeded(DEFAULT_CONTAINER, initial)
+    }
+
+    /// Create an empty KernCore whose text lives in the container `name`
+    ///
+    /// Lets several Kern documents share one Loro workspace. Peers must use
+    /// the same name to see each other's text.
+    pub fn with_container_name(name: &str) -> Self {
+        Self::seeded(name, "")
+    }
+
+    /// Like `with_container_name`, optionally as a plain-text field
+    ///
+    /// Unless `rich`, `mark_range`, `unmark` and marks in `from_json` fail
+    /// with `KernError::PlainContainer`. This is a local setting: each peer
+    /// opens the container with its own choice.
+    pub fn with_container(name: &str, rich: bool) -> Self {
+        let mut core = Self::seeded(name, "");
+        core.rich = rich;
+        core
+    }
+
+    /// Independent copy of this document under a fresh peer ID
+    ///
+    /// Merge back by exchanging `export_updates` output; the fork's update
+    /// cursor starts at the fork point so it only exports its own edits.
+    pub fn fork(&self) -> Self {
+        let doc = self.doc.fork();
+        doc.set_record_timestamp(true);
+        let mut fork = Self::from_doc(doc, &self.container);
+        fork.last_export = self.doc.oplog_vv();
+        fork.rich = self.rich;
+        fork
+    }
+
+    /// Hold subsequent edits pending until `end_batch` or `commit`, so they
+    /// land as a single commit and undo step
+    pub fn begin_batch(&mut self) {
+        self.batching = true;
+    }
+
+    /// Leave batch mode and commit everything held since `begin_batch`
+    pub fn end_batch(&mut self) {
+        self.batching = false;
+        self.flush();
+    }
+
+    /// Flush Loro's pending transaction
+    pub fn commit(&mut self) {
+        self.flush();
+    }
+
+    /// Commit any edits buffered by debounced mode
+    pub fn flush(&mut self) {
+        self.commit_pending(None);
+    }
+
+    /// Whether edits are waiting in an uncommitted transaction
+    pub fn has_uncommitted_changes(&self) -> bool {
+        self.doc.get_pending_txn_len() > 0
+    }
+
+    /// Flush pending ops as one commit carrying `message`
+    pub fn commit_with_message(&mut self, message: &str) {
+        self.commit_as(Some(message));
+    }
+
+    /// Encode a stable cursor anchored at `line`/`col` that survives remote edits
+    pub fn cursor_from_pos(&self, line: usize, col: usize) -> Result<Vec<u8>, KernError> {
+        let text = self.text();
+        let pos = char_offset(&text.to_string(), line, col);
+        text.get_cursor(pos, Side::Middle)
+            .map(|cursor| cursor.encode())
+            .ok_or_else(|| KernError::InvalidCursor(format!("no position {line}:{col}")))
+    }
+
+    /// Whether history has grown large enough relative to the content that
+    /// exporting a shallow snapshot is worthwhile
+    pub fn should_compact(&self) -> bool {
+        let ops = self.doc.len_ops();
+        let chars = self.text().len_unicode();
+        ops >= COMPACT_MIN_OPS && ops > chars.saturating_mul(COMPACT_OPS_PER_CHAR)
+    }
+
+    /// Drop all history in place, keeping the current content, marks and
+    /// metadata under a fresh peer ID
+    ///
+    /// The document is rebuilt from a shallow snapshot at the current
+    /// version, so peers can still merge edits made after this point. Undo
+    /// history, checkpoints and time-travel are lost, and change
+    /// subscriptions end. Fails with `KernError::Detached` while checked out.
+    pub fn compact(&mut self) -> Result<(), KernError> {
+        self.ensure_attached()?;
+        self.flush();
+        let frontiers = self.doc.oplog_frontiers();
+        let snapshot = self.doc.export(ExportMode::shallow_snapshot(&frontiers))?;
+        let doc = LoroDoc::new();
+        doc.set_record_timestamp(true);
+        doc.import(&snapshot)?;
+
+        let mut compacted = Self::from_doc(doc, &self.container);
+        compacted.version = self.version;
+        compacted.sync_callback = self.sync_callback.take();
+        compacted.commit_mode = self.commit_mode;
+        compacted.limits = self.limits;
+        compacted.undo_local_only = self.undo_local_only;
+        compacted.readonly = self.readonly;
+        compacted.rich = self.rich;
+        compacted.unsaved_bytes.store(
+            self.unsaved_bytes.load(atomic::Ordering::Relaxed),
+            atomic::Ordering::Relaxed,
+        );
+        *self = compacted;
+        Ok(())
+    }
+
+    /// Replace every match of `query` in one commit, returning the count
+    ///
+    /// Matches are replaced back to front so earlier offsets stay valid, and
+    /// the single commit makes the whole replacement one undo step.
+    pub fn replace_all(
+        &mut self,
+        query: &str,
+        replacement: &str,
+        case_sensitive: bool,
+    ) -> Result<usize, KernError> {
+        self.ensure_editable()?;
+
+        let matches = self.find(query, case_sensitive);
+        let text = self.text();
+        let content = text.to_string();
+        let splices: Vec<Splice> = matches
+            .iter()
+            .rev()
+            .map(|m| Splice {
+                start: char_offset(&content, m.line, m.col),
+                delete: m.length,
+                insert: (!replacement.is_empty()).then(|| replacement.to_string()),
+            })
+            .collect();
+        let len = splices
+            .iter()
+            .fold(text.len_unicode(), |len, splice| splice.len_after(len));
+        let ops = splices.iter().map(Splice::ops).sum::<usize>();
+        self.check_limits(len, ops + self.carry_ops(text.len_unicode(), &splices))?;
+        self.apply_splices(&text, &splices)?;
+
+        if !matches.is_empty() {
+            self.commit_as(None);
+        }
+        Ok(matches.len())
+    }
+
+    /// Replace the first match at or after `from`, returning the position
+    /// just past the replacement so a find bar can continue from there
+    ///
+    /// With `wrap`, the search continues from the top of the document when
+    /// nothing matches after `from`. Returns `None` if nothing matched.
+    pub fn replace_next(
+        &mut self,
+        query: &str,
+        replacement: &str,
+        from: Position,
+        case_sensitive: bool,
+        wrap: bool,
+    ) -> Result<Option<Position>, KernError> {
+        self.ensure_editable()?;
+
+        let matches = self.find(query, case_sensitive);
+        let after = |m: &&SearchMatch| (m.line, m.col) >= (from.line, from.col);
+        let Some(m) = matches
+            .iter()
+            .find(after)
+            .or(matches.first().filter(|_| wrap))
+        else {
+            return Ok(None);
+        };
+
+        let pos = self.offset_of(m.line, m.col)?;
+        self.splice_at(pos, m.length, replacement)?;
+        // `splice_at` normalizes line endings, so measure what went in
+        let end = pos + normalize_newlines(replacement).chars().count();
+        Ok(Some(line_col(&self.get_text(), end)))
+    }
+
+    /// Insert `text` at an absolute character offset
+    pub fn insert_at(&mut self, offset: usize, text: &str) -> Result<(), KernError> {
+        self.splice_at(offset, 0, text)
+    }
+
+    /// Delete `count` characters starting at an absolute character offset
+    pub fn delete_at(&mut self, offset: usize, count: usize) -> Result<(), KernError> {
+        self.splice_at(offset, count, "")
+    }
+
+    /// Character offset of a line/col position, with `col` clamped to the line
+    pub fn offset_of(&self, line: usize, col: usize) -> Result<usize, KernError> {
+        let content = self.get_text();
+        check_line(&content, line)?;
+        Ok(char_offset(&content, line, col))
+    }
+
+    /// Character offset of a position whose `col` counts grapheme clusters,
+    /// clamped to the line
+    pub fn grapheme_offset_of(&self, line: usize, col: usize) -> Result<usize, KernError> {
+        let content = self.get_text();
+        check_line(&content, line)?;
+        let chars = content
+            .split('\n')
+            .nth(line)
+            .unwrap_or_default()
+            .graphemes(true)
+            .take(col)
+            .map(|g| g.chars().count())
+            .sum();
+        Ok(char_offset(&content, line, chars))
+    }
+
+    /// Position of a character offset with `col` in grapheme clusters
+    ///
+    /// An offset inside a cluster snaps back to the cluster's start.
+    pub fn grapheme_position_of(&self, offset: usize) -> Result<Position, KernError> {
+        let content = self.get_text();
+        check_offset(&content, offset)?;
+        let Position { line, col } = line_col(&content, offset);
+        let mut chars = 0;
+        let col = content
+            .split('\n')
+            .nth(line)
+            .unwrap_or_default()
+            .graphemes(true)
+            .take_while(|g| {
+                chars += g.chars().count();
+                chars <= col
+            })
+            .count();
+        Ok(Position { line, col })
+    }
+
+    /// Number of grapheme clusters on `line`
+    ///
+    /// Differs from the line's `char_len` for combining marks and emoji
+    /// sequences, which Loro stores as several characters.
+    pub fn grapheme_len(&self, line: usize) -> Result<usize, KernError> {
+        let content = self.get_text();
+        check_line(&content, line)?;
+        Ok(content
+            .split('\n')
+            .nth(line)
+            .map_or(0, |l| l.graphemes(true).count()))
+    }
+
+    /// Line-ending style of the stored content: `"lf"`, `"crlf"` or `"mixed"`
+    pub fn detect_line_ending(&self) -> String {
+        line_ending_style(&self.get_text()).to_string()
+    }
+
+    /// Rewrite every line ending to `to` (`"lf"` or `"crlf"`) in place
+    ///
+    /// Lines are always split on LF, so a CR before it shows up as part of
+    /// the line's content; CRLF is meant for embeddings that hand the text to
+    /// CRLF tooling. Only local input such as `set_text` and pastes is
+    /// normalized to LF; imports apply as sent, so a conversion survives sync.
+    pub fn convert_line_endings(&mut self, to: &str) -> Result<(), KernError> {
+        let crlf = match to {
+            "lf" => false,
+            "crlf" => true,
+            _ => return Err(KernError::InvalidLineEnding(to.to_string())),
+        };
+        self.ensure_editable()?;
+
+        let text = self.text();
+        let splices = ending_splices(&text.to_string(), crlf);
+        if splices.is_empty() {
+            return Ok(());
+        }
+        let len = splices
+            .iter()
+            .fold(text.len_unicode(), |len, splice| splice.len_after(len));
+        self.check_limits(len, splices.iter().map(Splice::ops).sum())?;
+        self.apply_splices(&text, &splices)?;
+        self.commit_as(None);
+        Ok(())
+    }
+
+    /// Remove a formatting mark from the character range `[start, end)`
+    pub fn unmark(&mut self, start: usize, end: usize, key: &str) -> Result<(), KernError> {
+        self.ensure_editable()?;
+        self.ensure_rich()?;
+        self.check_op_limit(MARK_OPS)?;
+        self.text().unmark(start..end, key)?;
+        self.commit_as(None);
+        Ok(())
+    }
+
+    /// Set the block type of a line, which follows the line through edits
+    /// and merges
+    ///
+    /// Blocks are keyed by line ID, so the last line loses its type once a
+    /// newline is typed after it and it gets a terminated ID.
+    pub fn set_block_type(&mut self, line: usize, kind: &str) -> Result<(), KernError> {
+        if !BLOCK_TYPES.contains(&kind) {
+            return Err(KernError::InvalidBlockType(kind.to_string()));
+        }
+        self.ensure_editable()?;
+
+        let text = self.text();
+        let content = text.to_string();
+        check_line(&content, line)?;
+        let id = line_ids(&text, &content).swap_remove(line);
+        self.check_op_limit(1)?;
+        let blocks = self.doc.get_map(BLOCKS);
+        if kind == "paragraph" {
+            blocks.delete(&id)?;
+        } else {
+            blocks.insert(&id, kind)?;
+        }
+        self.commit_as(None);
+        Ok(())
+    }
+
+    /// Export full snapshot for periodic saves
+    pub fn export_snapshot(&self) -> Result<Vec<u8>, KernError> {
+        Ok(seal_snapshot(self.doc.export(ExportMode::Snapshot)?))
+    }
+
+    /// Export a snapshot headed by metadata that `snapshot_meta` can read
+    /// without loading the document
+    pub fn export_snapshot_with_meta(&self) -> Result<Vec<u8>, KernError> {
+        let header = serde_json::to_vec(&self.snapshot_meta())
+            .map_err(|e| KernError::Serialization(e.to_string()))?;
+        Ok(seal_with_header(
+            &header,
+            self.doc.export(ExportMode::Snapshot)?,
+        ))
+    }
+
+    /// Metadata describing the current state, as stored by
+    /// `export_snapshot_with_meta`
+    pub fn snapshot_meta(&self) -> SnapshotMeta {
+        let content = self.get_text();
+        let title = content
+            .lines()
+            .map(|line| line.trim_start_matches('#').trim())
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .to_string();
+        let mut peers: Vec<String> = self
+            .doc
+            .oplog_vv()
+            .keys()
+            .map(|peer| peer.to_string())
+            .collect();
+        peers.sort();
+        SnapshotMeta {
+            title,
+            char_count: content.chars().count(),
+            updated_at: self
+                .history()
+                .iter()
+                .map(|change| change.timestamp)
+                .max()
+                .unwrap_or(0),
+            peers,
+        }
+    }
+
+    /// Export a deflate-compressed snapshot for size-sensitive storage
+    pub fn export_snapshot_compressed(&self) -> Result<Vec<u8>, KernError> {
+        let snapshot = self.export_snapshot()?;
+        let mut blob = vec![COMPRESSED_MAGIC];
+        blob.extend(miniz_oxide::deflate::compress_to_vec(&snapshot, 6));
+        Ok(blob)
+    }
+
+    /// Export a snapshot that keeps only the most recent `depth` ops of history
+    ///
+    /// The current state is preserved and recent edits stay mergeable, but
+    /// undo and time-travel past the trim point are lost for whoever loads it.
+    /// Shallow snapshots can only be loaded into an empty document.
+    pub fn export_shallow_snapshot(&self, depth: usize) -> Result<Vec<u8>, KernError> {
+        let Some(frontier) = self.frontier_before_last_ops(depth) else {
+            // Nothing old enough to trim
+            return self.export_snapshot();
+        };
+        Ok(seal_snapshot(self.doc.export(
+            ExportMode::shallow_snapshot(&Frontiers::from_id(frontier)),
+        )?))
+    }
+
+    /// Export the full history in a byte-stable form for content addressing
+    ///
+    /// `export_snapshot` also encodes Loro's materialized state, whose layout
+    /// depends on the order ops were imported in, so converged peers can
+    /// produce different bytes. The update encoding is ordered by op ID and
+    /// matches for any two peers holding the same history. Peers that reached
+    /// the same text through different histories, or a shallow snapshot,
+    /// still differ. Loads with `load_from_bytes`.
+    pub fn export_canonical(&self) -> Result<Vec<u8>, KernError> {
+        Ok(seal_snapshot(self.doc.export(ExportMode::all_updates())?))
+    }
+
+    /// Export only the current text, marks and block types, without history
+    ///
+    /// The result is a brand-new document under a throwaway peer, so deleted
+    /// text, authors and timestamps don't leak to whoever receives it. It
+    /// shares no history with this one and can't be merged back into it.
+    pub fn export_redacted_snapshot(&self) -> Result<Vec<u8>, KernError> {
+        let redacted = LoroDoc::new();
+        let text = redacted.get_text(&*self.container);
+        text.apply_delta(&self.text().to_delta())?;
+
+        // Block types are keyed by newline op IDs, which are new here
+        let source = self.text();
+        let source_content = source.to_string();
+        let content = text.to_string();
+        let blocks = self.doc.get_map(BLOCKS);
+        let redacted_blocks = redacted.get_map(BLOCKS);
+        for (old, new) in line_ids(&source, &source_content)
+            .into_iter()
+            .zip(line_ids(&text, &content))
+        {
+            if let Some(ValueOrContainer::Value(kind)) = blocks.get(&old) {
+                redacted_blocks.insert(&new, kind)?;
+            }
+        }
+
+        redacted.commit();
+        Ok(seal_snapshot(redacted.export(ExportMode::Snapshot)?))
+    }
+
+    /// The content, marks and block types as plain data, without history
+    pub fn to_json(&self) -> DocumentJson {
+        let lines = self
+            .view_range(0, usize::MAX)
+            .lines
+            .into_iter()
+            .map(|line| JsonLine {
+                content: line.content,
+                marks: line.marks.unwrap_or_default(),
+                block: line.block,
+            })
+            .collect();
+        DocumentJson {
+            lines,
+            version: self.version,
+        }
+    }
+
+    /// Replace the content with `json` through ordinary CRDT ops, as one
+    /// commit and undo step
+    ///
+    /// Everything is validated before the document is touched. Comments
+    /// anchored in the old text lose their place.
+    pub fn from_json(&mut self, json: &DocumentJson) -> Result<(), KernError> {
+        self.ensure_editable()?;
+        for (i, line) in json.lines.iter().enumerate() {
+            if line.content.contains(['\n', '\r']) {
+                return Err(KernError::InvalidDocument(format!(
+                    "line {i} contains a line break"
+                )));
+            }
+            if !BLOCK_TYPES.contains(&line.block.as_str()) {
+                return Err(KernError::InvalidBlockType(line.block.clone()));
+            }
+            if !line.marks.is_empty() {
+                self.ensure_rich()?;
+            }
+            let len = line.content.chars().count();
+            if let Some(span) = line.marks.iter().find(|s| s.start >= s.end || s.end > len) {
+                return Err(KernError::OutOfRange(format!(
+                    "mark {}..{} on line {i} of length {len}",
+                    span.start, span.end
+                )));
+            }
+        }
+
+        let text = self.text();
+        let content = json
+            .lines
+            .iter()
+            .map(|line| line.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (old_len, new_len) = (text.len_unicode(), content.chars().count());
+        let blocks = self.doc.get_map(BLOCKS);
+        let marks: usize = json.lines.iter().map(|line| line.marks.len()).sum();
+        let typed = json.lines.iter().filter(|line| line.block != "paragraph");
+        // Rewrite the text, mark it, and swap every block type for the new ones
+        let ops = old_len + new_len + MARK_OPS * marks + blocks.len() + typed.count();
+        self.check_limits(new_len, ops)?;
+        text.delete(0, old_len)?;
+        text.insert(0, &content)?;
+
+        let mut line_start = 0;
+        for line in &json.lines {
+            for span in &line.marks {
+                let range = line_start + span.start..line_start + span.end;
+                text.mark(range, &span.key, span.value.clone())?;
+            }
+            line_start += line.content.chars().count() + 1;
+        }
+
+        // The old line IDs are gone along with the old newlines
+        clear_live(&blocks)?;
+        for (line, id) in json.lines.iter().zip(line_ids(&text, &content)) {
+            if line.block != "paragraph" {
+                blocks.insert(&id, line.block.as_str())?;
+            }
+        }
+
+        self.version = self.version.max(json.version);
+        self.commit_as(None);
+        Ok(())
+    }
+
+    /// Export only updates since last export (lightweight)
+    pub fn export_updates(&mut self) -> Result<Vec<u8>, KernError> {
+        let bytes = self.doc.export(ExportMode::updates(&self.last_export))?;
+        self.last_export = self.doc.oplog_vv();
+        Ok(bytes)
+    }
+
+    /// Forget the last export so the next `export_updates` ships full history
+    pub fn reset_update_cursor(&mut self) {
+        self.last_export = VersionVector::new();
+    }
+
+    /// Whether at least `threshold_bytes` of local edits, measured as
+    /// encoded updates, have been committed since `mark_saved`
+    ///
+    /// Edits still held by a batch or debounced mode don't count until they
+    /// commit, and imported ops never do: they came from a peer that has
+    /// them already.
+    pub fn autosave_due(&self, threshold_bytes: usize) -> bool {
+        let unsaved = self.unsaved_bytes.load(atomic::Ordering::Relaxed);
+        unsaved > 0 && unsaved >= threshold_bytes
+    }
+
+    /// Reset the `autosave_due` counter after a successful persist
+    pub fn mark_saved(&mut self) {
+        self.unsaved_bytes.store(0, atomic::Ordering::Relaxed);
+    }
+
+    /// Encode the oplog version vector for sync negotiation
+    pub fn get_version_vector(&self) -> Vec<u8> {
+        self.doc.oplog_vv().encode()
+    }
+
+    /// Export the updates a peer is missing, given its encoded version vector
+    pub fn export_from_version_vector(&self, vv: &[u8]) -> Result<Vec<u8>, KernError> {
+        let from = VersionVector::decode(vv)?;
+        Ok(self.doc.export(ExportMode::updates(&from))?)
+    }
+
+    /// Encode the frontier of the currently visible state
+    pub fn current_frontier(&self) -> Vec<u8> {
+        self.doc.state_frontiers().encode()
+    }
+
+    /// Check the document out to a past frontier (read-only until returning)
+    pub fn checkout(&mut self, frontier: &[u8]) -> Result<(), KernError> {
+        let frontiers = Frontiers::decode(frontier)?;
+        self.doc.checkout(&frontiers)?;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Whether the document is checked out to a past version, which is
+    /// read-only
+    ///
+    /// Every edit fails with `KernError::Detached` until `checkout_to_latest`.
+    pub fn is_detached(&self) -> bool {
+        self.doc.is_detached()
+    }
+
+    /// Fail with `KernError::Detached` while checked out to a past version
+    fn ensure_attached(&self) -> Result<(), KernError> {
+        if self.doc.is_detached() {
+            return Err(KernError::Detached);
+        }
+        Ok(())
+    }
+
+    /// Lock or unlock local editing, independently of time travel
+    ///
+    /// While locked, every edit fails with `KernError::ReadOnly`. Imports
+    /// still apply, so a viewer keeps receiving remote updates.
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    /// Whether local editing is locked by `set_readonly`
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Fail with `KernError::PlainContainer` unless the container takes marks
+    fn ensure_rich(&self) -> Result<(), KernError> {
+        if !self.rich {
+            return Err(KernError::PlainContainer(self.container.clone()));
+        }
+        Ok(())
+    }
+
+    /// Fail unless local edits are allowed: not read-only, nor detached
+    fn ensure_editable(&self) -> Result<(), KernError> {
+        if self.readonly {
+            return Err(KernError::ReadOnly);
+        }
+        self.ensure_attached()
+    }
+
+    /// Return from a past version to the live head
+    pub fn checkout_to_latest(&mut self) {
+        self.doc.checkout_to_latest();
+        self.version += 1;
+    }
+
+    /// Bookmark the current version under `name` (synced with the document)
+    pub fn create_checkpoint(&mut self, name: &str) -> Result<(), KernError> {
+        self.ensure_editable()?;
+        // Flush any open batch so it's covered and stays undoable
+        self.doc.commit();
+        let frontier = self.doc.oplog_frontiers().encode();
+        self.check_bare_op_limit(1)?;
+        self.doc.get_map(CHECKPOINTS).insert(name, frontier)?;
+        self.doc
+            .commit_with(CommitOptions::new().origin(META_ORIGIN));
+        Ok(())
+    }
+
+    /// Names of all checkpoints, sorted
+    pub fn list_checkpoints(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .doc
+            .get_map(CHECKPOINTS)
+            .keys()
+            .map(|k| k.to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Check out the version saved under `name`; read-only until
+    /// `checkout_to_latest`
+    pub fn restore_checkpoint(&mut self, name: &str) -> Result<(), KernError> {
+        let frontier = self.checkpoint_frontier(name)?;
+        self.checkout(&frontier)
+    }
+
+    /// What restoring checkpoint `name` would change, from the current text
+    /// to the checkpoint's, without checking it out
+    pub fn diff_against_checkpoint(&self, name: &str) -> Result<Vec<LineChange>, KernError> {
+        let frontier = Frontiers::decode(&self.checkpoint_frontier(name)?)?;
+        let past = self.doc.fork_at(&frontier);
+        let restored = past.get_text(self.container.as_str()).to_string();
+        Ok(line_diff(&self.get_text(), &restored))
+    }
+
+    /// The ops that buffered imports are waiting for, empty when nothing is
+    /// pending
+    ///
+    /// Loro holds back imported ops until their causal predecessors arrive.
+    /// This reports the gap before each peer's held-back ops; a wait on some
+    /// other peer's ops can't be seen and isn't listed.
+    pub fn pending_dependencies(&self) -> Vec<PendingDependency> {
+        let vv = self.doc.oplog_vv();
+        self.awaiting
+            .iter()
+            .filter_map(|(&peer, &end)| {
+                let start = vv.get(&peer).copied().unwrap_or(0);
+                (start < end).then(|| PendingDependency {
+                    peer: peer.to_string(),
+                    start,
+                    end,
+                })
+            })
+            .collect()
+    }
+
+    /// Start a chunked import for payloads streamed in pieces
+    pub fn begin_import(&self) -> ImportSession {
+        ImportSession { buffer: Vec::new() }
+    }
+
+    /// `export_snapshot` split into pieces of at most `chunk_size` bytes,
+    /// which concatenate back to exactly the single-shot snapshot
+    pub fn export_snapshot_chunked(&self, chunk_size: usize) -> Result<SnapshotChunks, KernError> {
+        if chunk_size == 0 {
+            return Err(KernError::OutOfRange(
+                "chunk size must be positive".to_string(),
+            ));
+        }
+        let snapshot = self.export_snapshot()?;
+        Ok(SnapshotChunks {
+            total_len: snapshot.len(),
+            chunks: snapshot.chunks(chunk_size).map(<[u8]>::to_vec).collect(),
+        })
+    }
+
+    /// Get current version number
+    ///
+    /// The clock is stored in the document with each local commit, so it
+    /// resumes after a reload, and imports advance it past incoming values.
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    /// When the document was created, in Unix seconds
+    ///
+    /// Recorded by `new` and `with_content`, or by the first edit when they
+    /// were seeded empty; `None` until then.
+    pub fn created_at(&self) -> Option<i64> {
+        match self.doc.get_map(META).get(CREATED_AT_KEY) {
+            Some(ValueOrContainer::Value(LoroValue::I64(secs))) => Some(secs),
+            _ => None,
+        }
+    }
+
+    /// Number of commits made to the document, including undo and redo
+    ///
+    /// Unlike `get_version` it only counts commits, and it is stored in the
+    /// document so it survives export and import. Merges keep the larger
+    /// count of the two sides.
+    pub fn revision_count(&self) -> u64 {
+        self.revisions
+    }
+
+    /// Get document content as plain text
+    pub fn get_text(&self) -> String {
+        self.get_text_of(&self.container)
+    }
+
+    /// Serialize the document to Markdown, rendering formatting marks as
+    /// inline syntax (`**bold**`, `*italic*`, links, ...)
+    pub fn to_markdown(&self) -> String {
+        markdown(&self.text())
+    }
+
+    /// Document content with all formatting stripped
+    pub fn to_plain_text(&self) -> String {
+        // Marks live beside the text in Loro, so the raw string is already clean
+        self.get_text()
+    }
+
+    /// Blocks of consecutive non-blank lines, with fenced code blocks kept
+    /// whole even across blank lines
+    pub fn paragraphs(&self) -> Vec<Paragraph> {
+        split_paragraphs(&self.get_text())
+    }
+
+    /// Get a single line's content, or `None` past the end of the document
+    pub fn get_line(&self, index: usize) -> Option<String> {
+        self.get_text().split('\n').nth(index).map(str::to_string)
+    }
+
+    /// Number of lines in the document
+    pub fn get_line_count(&self) -> usize {
+        self.get_text().split('\n').count()
+    }
+
+    /// Get the content of a named text container as plain text
+    pub fn get_text_of(&self, container: &str) -> String {
+        self.doc.get_text(container).to_string()
+    }
+
+    /// Names of all root text containers in the document
+    pub fn list_containers(&self) -> Vec<String> {
+        let LoroValue::Map(roots) = self.doc.get_value() else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = roots
+            .iter()
+            .filter(|(_, value)| {
+                matches!(
+                    value,
+                    LoroValue::Container(id) if id.container_type() == ContainerType::Text
+                )
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Every root container (texts, maps, lists) as one value, keyed by name
+    ///
+    /// A debugging aid for inspecting merged structure; it copies the whole
+    /// document, so it can be large and isn't for hot paths.
+    pub fn deep_value(&self) -> LoroValue {
+        self.doc.get_deep_value()
+    }
+
+    /// Empty the text container `name` with ordinary delete ops, so the
+    /// clearing merges with and syncs to other peers like any edit
+    ///
+    /// Clearing the engine's own container also drops its block types,
+    /// leaving a valid empty document. Fails with
+    /// `KernError::UnknownContainer` if no such container exists.
+    pub fn clear_container(&mut self, name: &str) -> Result<(), KernError> {
+        self.ensure_editable()?;
+        if name != self.container && !self.list_containers().iter().any(|c| c == name) {
+            return Err(KernError::UnknownContainer(name.to_string()));
+        }
+        let text = self.doc.get_text(name);
+        let blocks = self.doc.get_map(BLOCKS);
+        let (len, cleared_blocks) = if name == self.container {
+            (0, blocks.len())
+        } else {
+            (self.text().len_unicode(), 0)
+        };
+        self.check_limits(len, text.len_unicode() + cleared_blocks)?;
+        text.delete(0, text.len_unicode())?;
+        if cleared_blocks > 0 {
+            clear_live(&blocks)?;
+        }
+        self.commit_as(None);
+        Ok(())
+    }
+
+    /// Empty the text container `name` and hide it from this peer's deep
+    /// value
+    ///
+    /// Loro can't delete root containers outright: the hiding is local and
+    /// only lasts while the container stays empty, so editing the name again,
+    /// here or on any peer, brings it back.
+    pub fn remove_container(&mut self, name: &str) -> Result<(), KernError> {
+        self.clear_container(name)?;
+        self.doc.delete_root_container(self.doc.get_text(name).id());
+        self.doc.commit();
+        Ok(())
+    }
+
+    /// Set entire document content
+    ///
+    /// This is `set_text_diffed`, so a concurrent `set_text` on another peer
+    /// merges like any pair of concurrent edits: text both sides kept appears
+    /// once, changes to different regions both apply, and two replacements of
+    /// the same region are both kept, ordered by peer. Replacing everything
+    /// outright would instead leave both full contents back to back.
+    pub fn set_text(&mut self, content: &str) -> Result<(), KernError> {
+        self.set_text_diffed(content)
+    }
+
+    /// Replace the document content by applying only the minimal set of
+    /// inserts and deletes, so unchanged text keeps its CRDT identity
+    pub fn set_text_diffed(&mut self, content: &str) -> Result<(), KernError> {
+        self.apply_external_text(content).map(|_| ())
+    }
+
+    /// Replace the content with a full new text, e.g. from a formatter or
+    /// assistant, applying a minimal diff as one commit and undo step
+    ///
+    /// Unchanged text keeps its CRDT identity, so cursors and comments stay
+    /// put and the update stays small. Returns the changed hunks in
+    /// document order, each `start` counted in the new text.
+    pub fn apply_external_text(&mut self, new_text: &str) -> Result<Vec<LineRange>, KernError> {
+        self.ensure_editable()?;
+
+        let text = self.text();
+        let old = text.to_string();
+        let content = normalize_newlines(new_text);
+        if old == content {
+            return Ok(Vec::new());
+        }
+        let splices = splice_diff(&old, &content);
+        let ops = splices.iter().map(Splice::ops).sum::<usize>();
+        self.check_limits(
+            content.chars().count(),
+            ops + self.carry_ops(text.len_unicode(), &splices),
+        )?;
+        self.apply_splices(&text, &splices)?;
+        self.commit_as(None);
+        Ok(line_hunks(&old, &content))
+    }
+
+    /// Stop delivering change notifications, including character deltas
+    pub fn unsubscribe(&mut self) {
+        self.subscription = None;
+        self.delta_subscription = None;
+    }
+
+    /// Get the Loro peer ID as a string (u64 doesn't fit a JS number)
+    pub fn get_peer_id(&self) -> String {
+        self.doc.peer_id().to_string()
+    }
+
+    /// Set the Loro peer ID; must be called before any pending local ops
+    pub fn set_peer_id(&mut self, id: &str) -> Result<(), KernError> {
+        let peer: u64 = id
+            .parse()
+            .map_err(|_| KernError::InvalidPeerId(id.to_string()))?;
+        if self.doc.get_pending_txn_len() > 0 {
+            return Err(KernError::PendingOps);
+        }
+        Ok(self.doc.set_peer_id(peer)?)
+    }
+
+    /// Encode every known peer's presence for broadcasting
+    pub fn encode_awareness(&self) -> Vec<u8> {
+        self.awareness.encode_all()
+    }
+
+    /// Choose whether `undo` reverts only this peer's edits or every change
+    ///
+    /// Local-only scope is the default and skips remote edits merged in
+    /// between. Undoing everything steps back through the shared history
+    /// newest change first, writing each step as a new local edit to the
+    /// text and block types; checkpoints, comments and metadata are kept.
+    /// Earlier undo steps count as changes too, and Loro may have merged
+    /// consecutive local edits into one.
+    pub fn set_undo_scope(&mut self, local_only: bool) {
+        self.undo_local_only = local_only;
+        self.global_undo = None;
+    }
+
+    /// Undo the last change in scope, returning whether anything was undone
+    pub fn undo(&mut self) -> Result<bool, KernError> {
+        self.ensure_editable()?;
+        if !self.undo_local_only {
+            return self.step_global_undo(false);
+        }
+        let undone = self.undo.undo()?;
+        if undone {
+            self.version += 1;
+            self.persist_version_meta();
+        }
+        Ok(undone)
+    }
+
+    /// Redo the last undone change, returning whether anything was redone
+    pub fn redo(&mut self) -> Result<bool, KernError> {
+        self.ensure_editable()?;
+        if !self.undo_local_only {
+            return self.step_global_undo(true);
+        }
+        let redone = self.undo.redo()?;
+        if redone {
+            self.version += 1;
+            self.persist_version_meta();
+        }
+        Ok(redone)
+    }
+
+    /// Whether there is a change to undo
+    pub fn can_undo(&self) -> bool {
+        if self.undo_local_only {
+            return self.undo.can_undo();
+        }
+        match self.current_global_undo() {
+            Some(session) => session.position > 0,
+            None => !self.doc.oplog_frontiers().is_empty(),
+        }
+    }
+
+    /// Whether there is a change to redo
+    pub fn can_redo(&self) -> bool {
+        if self.undo_local_only {
+            return self.undo.can_redo();
+        }
+        self.current_global_undo()
+            .is_some_and(|session| session.position + 1 < session.steps.len())
+    }
+
+    /// The "undo everything" session, unless edits since have ended it
+    fn current_global_undo(&self) -> Option<&GlobalUndo> {
+        self.global_undo
+            .as_ref()
+            .filter(|session| session.heads == self.doc.oplog_frontiers())
+    }
+
+    /// Move one step back (or forward, for `redo`) through the shared history
+    fn step_global_undo(&mut self, redo: bool) -> Result<bool, KernError> {
+        // Flush any open batch so it's part of the history being walked
+        self.doc.commit();
+        let mut session = match self.global_undo.take() {
+            Some(session) if session.heads == self.doc.oplog_frontiers() => session,
+            _ => {
+                let steps = self.history_steps();
+                GlobalUndo {
+                    position: steps.len() - 1,
+                    steps,
+                    heads: Frontiers::default(),
+                }
+            }
+        };
+        // Skip bookkeeping-only changes, which show no difference
+        let mut target = None;
+        let mut position = session.position;
+        while let Some(next) = if redo {
+            Some(position + 1).filter(|&p| p < session.steps.len())
+        } else {
+            position.checked_sub(1)
+        } {
+            let visible = self.shows_difference(&session.steps[position], &session.steps[next]);
+            position = next;
+            if visible {
+                target = Some(next);
+                break;
+            }
+        }
+        let Some(position) = target else {
+            session.heads = self.doc.oplog_frontiers();
+            self.global_undo = Some(session);
+            return Ok(false);
+        };
+
+        self.revert_content(&session.steps[position])?;
+        self.version += 1;
+        self.persist_version_meta();
+        session.position = position;
+        session.heads = self.doc.oplog_frontiers();
+        self.global_undo = Some(session);
+        Ok(true)
+    }
+
+    /// Bring the text and block types back to how they were at `target`,
+    /// leaving checkpoints, comments and metadata as they are now
+    fn revert_content(&self, target: &Frontiers) -> Result<(), KernError> {
+        let (text, blocks) = (self.text().id(), self.doc.get_map(BLOCKS).id());
+        let diff = self.doc.diff(&self.doc.state_frontiers(), target)?;
+        let mut content = DiffBatch::default();
+        for (id, change) in diff.iter() {
+            if *id == text || *id == blocks {
+                // Each container appears once in a diff, so this can't clash
+                let _ = content.push(id.clone(), change.clone());
+            }
+        }
+        self.doc.apply_diff(content)?;
+        Ok(())
+    }
+
+    /// Whether the text or block types differ between two versions
+    fn shows_difference(&self, a: &Frontiers, b: &Frontiers) -> bool {
+        let text = self.text().id();
+        let blocks = self.doc.get_map(BLOCKS).id();
+        self.doc.diff(a, b).map_or(true, |diff| {
+            diff.iter().any(|(id, _)| *id == text || *id == blocks)
+        })
+    }
+
+    /// Frontier after each prefix of the history in causal order, starting
+    /// from the empty document
+    fn history_steps(&self) -> Vec<Frontiers> {
+        let heads: Vec<ID> = self.doc.oplog_frontiers().iter().collect();
+        let mut changes = Vec::new();
+        // Only fails for heads missing from the oplog, which ours can't be
+        let _ = self.doc.travel_change_ancestors(&heads, &mut |change| {
+            changes.push((change.lamport, change.id, change.len));
+            ControlFlow::Continue(())
+        });
+        changes.sort();
+
+        let mut vv = VersionVector::new();
+        let mut steps = vec![Frontiers::default()];
+        for (_, id, len) in changes {
+            vv.extend_to_include_last_id(ID::new(id.peer, id.counter + len as i32 - 1));
+            steps.push(self.doc.vv_to_frontiers(&vv));
+        }
+        steps
+    }
+
+    fn seeded(container: &str, initial: &str) -> Self {
+        let doc = LoroDoc::new();
+        doc.set_record_timestamp(true);
+
+        // Initialize with a text container for the document content
+        let text = doc.get_text(container);
+        let initial = normalize_newlines(initial);
+        if !initial.is_empty() {
+            // Inserting at 0 of a fresh, empty container can't fail
+            let _ = text.insert(0, &initial);
+            // Neither can writing to a fresh map
+            let _ = doc.get_map(META).insert(CREATED_AT_KEY, now_secs());
+            doc.commit();
+        }
+
+        Self::from_doc(doc, container)
+    }
+
+    /// Word, character and line counts of the content
+    pub fn stats(&self) -> TextStats {
+        TextStats::of(&self.get_text())
+    }
+
+    /// Word, character and line counts of one named text container
+    ///
+    /// Fails with `KernError::UnknownContainer` unless `list_containers`
+    /// includes it.
+    pub fn stats_of(&self, container: &str) -> Result<TextStats, KernError> {
+        if !self.list_containers().iter().any(|name| name == container) {
+            return Err(KernError::UnknownContainer(container.to_string()));
+        }
+        Ok(TextStats::of(&self.get_text_of(container)))
+    }
+
+    /// Size of the oplog, for deciding when to compact
+    pub fn oplog_stats(&self) -> OplogStats {
+        OplogStats::of(&self.doc)
+    }
+
+    /// Wrap `doc`, whose existing history won't be undoable
+    fn from_doc(doc: LoroDoc, container: &str) -> Self {
+        let mut undo = UndoManager::new(&doc);
+        undo.add_exclude_origin_prefix(META_ORIGIN);
+
+        let version = stored_version(&doc);
+        let revisions = stored_revisions(&doc);
+        let unsaved_bytes = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&unsaved_bytes);
+        let _save_tracking = doc.subscribe_local_update(Box::new(move |update| {
+            counter.fetch_add(update.len(), atomic::Ordering::Relaxed);
+            true
+        }));
+        KernCore {
+            doc,
+            undo,
+            version,
+            revisions,
+            awaiting: BTreeMap::new(),
+            last_export: VersionVector::new(),
+            subscription: None,
+            delta_subscription: None,
+            awareness: EphemeralStore::new(AWARENESS_TIMEOUT_MS),
+            sync_callback: None,
+            batching: false,
+            commit_mode: CommitMode::Immediate,
+            limits: Limits::default(),
+            held: 0,
+            view_cache: RefCell::new(None),
+            served_views: RefCell::new(VecDeque::new()),
+            container: container.to_string(),
+            rich: true,
+            undo_local_only: true,
+            readonly: false,
+            unsaved_bytes,
+            _save_tracking,
+            global_undo: None,
+        }
+    }
+
+    /// The text container this engine operates on
+    fn text(&self) -> LoroText {
+        self.doc.get_text(&*self.container)
+    }
+
+    /// Apply a decoded edit delta, returning the line range it touched
+    ///
+    /// `delete` characters before `line`/`col` are removed first, then
+    /// `insert` goes in at the start of the removed span, so the delta reads
+    /// as "replace the text before the caret". Fails with
+    /// `KernError::Detached` while checked out to a past version.
+    pub fn apply_delta(&mut self, edit: &EditDelta) -> Result<LineRange, KernError> {
+        self.apply_delta_as(edit, None)
+    }
+
+    /// Apply a decoded edit delta as `apply_delta_as` does, also returning
+    /// where the caret lands: just after the inserted text
+    pub fn apply_delta_with_caret(
+        &mut self,
+        edit: &EditDelta,
+        author: Option<&str>,
+    ) -> Result<EditResult, KernError> {
+        // Resolved the same way `apply_delta_as` resolves it, so the caret
+        // lands past exactly the text that went in
+        let (splice, _) = Splice::plan(&self.get_text(), edit)?;
+        let lines = self.apply_delta_as(edit, author)?;
+
+        let offset = splice.start + splice.inserted();
+        let Position { line, col } = line_col(&self.get_text(), offset);
+        Ok(EditResult {
+            lines,
+            caret: Caret { line, col, offset },
+        })
+    }
+
+    /// Apply a decoded edit delta, recording `author` as its commit message
+    pub fn apply_delta_as(
+        &mut self,
+        edit: &EditDelta,
+        author: Option<&str>,
+    ) -> Result<LineRange, KernError> {
+        let container = self.container.clone();
+        self.edit_container(&container, edit, author)
+    }
+
+    /// Apply a decoded edit delta to a named text container
+    pub fn apply_delta_to(
+        &mut self,
+        container: &str,
+        edit: &EditDelta,
+    ) -> Result<LineRange, KernError> {
+        self.edit_container(container, edit, None)
+    }
+
+    /// Insert possibly multi-line `text` at the caret as one edit and undo step
+    ///
+    /// Line endings are normalized to LF first. Pasting mid-line splits the
+    /// line around the pasted text; the caret ends up just past it.
+    pub fn paste(&mut self, line: usize, col: usize, text: &str) -> Result<PasteResult, KernError> {
+        let start = self.offset_of(line, col)?;
+        let text = normalize_newlines(text);
+        self.apply_delta(&EditDelta {
+            line,
+            col,
+            insert: Some(text.to_string()),
+            delete: None,
+        })?;
+        let caret = line_col(&self.get_text(), start + text.chars().count());
+        Ok(PasteResult {
+            caret,
+            start_line: line,
+            end_line: caret.line,
+        })
+    }
+
+    /// Delete from the caret to the previous or next word boundary, as for
+    /// Ctrl+Backspace and Ctrl+Delete
+    ///
+    /// Boundaries follow Unicode word segmentation, and whitespace next to
+    /// the caret goes along with the word beyond it. At the start or end of
+    /// a line the newline is deleted instead, joining the lines.
+    pub fn delete_word(
+        &mut self,
+        line: usize,
+        col: usize,
+        forward: bool,
+    ) -> Result<LineRange, KernError> {
+        let content = self.get_text();
+        check_line(&content, line)?;
+        let lines: Vec<&str> = content.split('\n').collect();
+        let line_len = lines[line].chars().count();
+        let col = col.min(line_len);
+
+        // Expressed as a backspace from the far end of the deleted span
+        let edit = match (forward, col) {
+            (false, 0) if line == 0 => return Ok(LineRange::default()),
+            (false, 0) => EditDelta {
+                line,
+                col: 0,
+                insert: None,
+                delete: Some(1),
+            },
+            (false, _) => EditDelta {
+                line,
+                col,
+                insert: None,
+                delete: Some(col - word_edge(lines[line], col, false)),
+            },
+            (true, _) if col == line_len && line + 1 == lines.len() => {
+                return Ok(LineRange::default())
+            }
+            (true, _) if col == line_len => EditDelta {
+                line: line + 1,
+                col: 0,
+                insert: None,
+                delete: Some(1),
+            },
+            (true, _) => {
+                let end = word_edge(lines[line], col, true);
+                EditDelta {
+                    line,
+                    col: end,
+                    insert: None,
+                    delete: Some(end - col),
+                }
+            }
+        };
+        self.apply_delta(&edit)
+    }
+
+    /// Columns of the word under the caret, by Unicode word segmentation
+    ///
+    /// On whitespace that is the whole whitespace run; on punctuation, the
+    /// single mark. At the end of a line it's the word just before the caret.
+    pub fn word_range_at(&self, line: usize, col: usize) -> Result<ColumnRange, KernError> {
+        let content = self.get_text();
+        check_line(&content, line)?;
+        let segments = word_segments(content.split('\n').nth(line).unwrap_or_default());
+        let (start_col, end_col, _) = segments
+            .iter()
+            .find(|&&(start, end, _)| start <= col && col < end)
+            .or(segments.last())
+            .copied()
+            .unwrap_or((0, 0, true));
+        Ok(ColumnRange { start_col, end_col })
+    }
+
+    /// Columns spanning all of `line`, excluding its line break
+    pub fn line_range_at(&self, line: usize) -> Result<ColumnRange, KernError> {
+        let content = self.get_text();
+        check_line(&content, line)?;
+        let len = content
+            .split('\n')
+            .nth(line)
+            .map_or(0, |l| l.chars().count());
+        Ok(ColumnRange {
+            start_col: 0,
+            end_col: len,
+        })
+    }
+
+    /// Apply `splices` in order to the engine's text, moving the last line's
+    /// block type onto the newline typed at its end
+    fn apply_splices(&self, text: &LoroText, splices: &[Splice]) -> Result<(), KernError> {
+        let blocks = self.doc.get_map(BLOCKS);
+        for splice in splices {
+            let carried = match splice.breaks_end(text.len_unicode()) {
+                true => blocks.get("end"),
+                false => None,
+            };
+            splice.apply(text)?;
+            if let Some(ValueOrContainer::Value(kind)) = carried {
+                let head = splice.insert.as_deref().and_then(|s| s.split('\n').next());
+                let newline = splice.start + head.map_or(0, |head| head.chars().count());
+                blocks.insert(&line_id(text, newline), kind)?;
+                blocks.delete("end")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ops `apply_splices` adds beyond the splices' own, for a text `len` long
+    fn carry_ops(&self, mut len: usize, splices: &[Splice]) -> usize {
+        if self.doc.get_map(BLOCKS).get("end").is_none() {
+            return 0;
+        }
+        for splice in splices {
+            if splice.breaks_end(len) {
+                return 2;
+            }
+            len = splice.len_after(len);
+        }
+        0
+    }
+
+    fn edit_container(
+        &mut self,
+        container: &str,
+        edit: &EditDelta,
+        author: Option<&str>,
+    ) -> Result<LineRange, KernError> {
+        self.ensure_editable()?;
+
+        let text = self.doc.get_text(container);
+        let (splice, affected) = Splice::plan(&text.to_string(), edit)?;
+        let len = text.len_unicode();
+        let splices = std::slice::from_ref(&splice);
+        // Block types belong to the engine's container alone
+        let main = container == self.container;
+        let carried = if main {
+            self.carry_ops(len, splices)
+        } else {
+            0
+        };
+        self.check_limits(splice.len_after(len), splice.ops() + carried)?;
+        if main {
+            self.apply_splices(&text, splices)?;
+        } else {
+            splice.apply(&text)?;
+        }
+
+        // Commit per edit so each one is its own undo step
+        self.commit_as(author);
+        if container == self.container {
+            self.patch_view_cache(&text, edit);
+        }
+
+        Ok(affected)
+    }
+
+    /// Bring a cache built just before `edit` up to date by rebuilding only
+    /// the edited line, or drop it if the edit may have split or joined lines
+    fn patch_view_cache(&self, text: &LoroText, edit: &EditDelta) {
+        let mut cache = self.view_cache.borrow_mut();
+        let Some(c) = cache.as_mut().filter(|c| c.version + 1 == self.version) else {
+            return;
+        };
+        let patched = c.lines.get(edit.line).and_then(|line| {
+            let col = edit.col.min(line.char_len);
+            let deleted = edit.delete.unwrap_or(0);
+            let inserted = edit.insert.as_deref().unwrap_or("");
+            if deleted > col || inserted.contains('\n') {
+                return None;
+            }
+            let start: usize = c.lines[..edit.line].iter().map(|l| l.char_len + 1).sum();
+            let end = start + line.char_len - deleted + inserted.chars().count();
+            let content = text.slice(start, end).ok()?;
+            let deltas = text.slice_delta(start, end, PosType::Unicode).ok()?;
+            let marks = delta_marks(deltas)
+                .into_iter()
+                .next()
+                .filter(|spans| !spans.is_empty());
+            Some(LineView {
+                id: line.id.clone(),
+                char_len: end - start,
+                content,
+                styled: marks.is_some(),
+                marks,
+                block: line.block.clone(),
+                visual_rows: None,
+            })
+        });
+        match patched {
+            Some(line) => {
+                c.lines[edit.line] = line;
+                c.version = self.version;
+            }
+            None => *cache = None,
+        }
+    }
+
+    /// Apply several deltas to the engine's container as one commit
+    ///
+    /// Deltas apply in order, each positioned against the text as left by the
+    /// ones before it, and the whole batch is a single undo step. If any delta
+    /// is invalid, nothing is applied and `KernError::BatchFailed` names it.
+    pub fn apply_deltas(&mut self, edits: &[EditDelta]) -> Result<LineRange, KernError> {
+        self.apply_deltas_as(edits, None)
+    }
+
+    /// Apply several deltas as one commit attributed to `author`
+    pub fn apply_deltas_as(
+        &mut self,
+        edits: &[EditDelta],
+        author: Option<&str>,
+    ) -> Result<LineRange, KernError> {
+        self.ensure_editable()?;
+
+        // Resolve every delta against a plain copy first, since Loro can't
+        // roll back ops once applied; a bad delta then leaves the document
+        // untouched
+        let text = self.text();
+        let before = text.to_string();
+        let mut after = before.clone();
+        let (mut len, mut ops) = (text.len_unicode(), 0);
+        let mut splices = Vec::with_capacity(edits.len());
+        for (index, edit) in edits.iter().enumerate() {
+            let (splice, _) =
+                Splice::plan(&after, edit).map_err(|error| KernError::BatchFailed {
+                    index,
+                    error: Box::new(error),
+                })?;
+            splice.apply_to(&mut after);
+            len = splice.len_after(len);
+            ops += splice.ops();
+            splices.push(splice);
+        }
+        self.check_limits(len, ops + self.carry_ops(text.len_unicode(), &splices))?;
+        self.apply_splices(&text, &splices)?;
+
+        self.commit_as(author);
+
+        Ok(LineRange::between(&before, &after))
+    }
+
+    /// Line/col position of a character offset
+    pub fn position_of(&self, offset: usize) -> Result<Position, KernError> {
+        let content = self.get_text();
+        check_offset(&content, offset)?;
+        Ok(line_col(&content, offset))
+    }
+
+    /// Replace `count` characters at `offset` with `insert` as one commit
+    fn splice_at(&mut self, offset: usize, count: usize, insert: &str) -> Result<(), KernError> {
+        self.ensure_editable()?;
+
+        let text = self.text();
+        let len = text.len_unicode();
+        if offset.saturating_add(count) > len {
+            return Err(KernError::OutOfRange(format!(
+                "characters {offset}..{} of {len}",
+                offset.saturating_add(count)
+            )));
+        }
+        let insert = normalize_newlines(insert);
+        let inserted = insert.chars().count();
+        self.check_limits(len - count + inserted, count + inserted)?;
+        text.splice(offset, count, &insert)?;
+        self.commit_as(None);
+        Ok(())
+    }
+
+    /// Delete the text between `a` and `b` as one op, swapping them if reversed
+    pub fn delete_between(&mut self, a: Position, b: Position) -> Result<LineRange, KernError> {
+        self.ensure_editable()?;
+
+        let text = self.text();
+        let content = text.to_string();
+        check_line(&content, a.line)?;
+        check_line(&content, b.line)?;
+        let from = char_offset(&content, a.line, a.col);
+        let to = char_offset(&content, b.line, b.col);
+        let (from, to) = (from.min(to), from.max(to));
+        if from == to {
+            return Ok(LineRange::default());
+        }
+        self.check_limits(text.len_unicode() - (to - from), to - from)?;
+        text.delete(from, to - from)?;
+        self.commit_as(None);
+
+        let first = a.line.min(b.line);
+        Ok(LineRange {
+            start: first,
+            end: first + 1,
+            inserted: 1,
+            removed: a.line.abs_diff(b.line) + 1,
+        })
+    }
+
+    /// Move line `from` so it ends up at index `to`, returning the lines
+    /// between the two
+    ///
+    /// The line is deleted and reinserted, keeping its marks and block type,
+    /// as one commit. If two peers move the same line concurrently, both
+    /// reinsertions survive the merge, so the line is duplicated, never lost.
+    pub fn move_line(&mut self, from: usize, to: usize) -> Result<LineRange, KernError> {
+        self.ensure_editable()?;
+
+        let text = self.text();
+        let content = text.to_string();
+        check_line(&content, from)?;
+        check_line(&content, to)?;
+        let affected = LineRange::rewritten(from.min(to), from.abs_diff(to) + 1);
+        if from == to {
+            return Ok(affected);
+        }
+
+        let last = content.split('\n').count() - 1;
+        let start = char_offset(&content, from, 0);
+        let len = content
+            .split('\n')
+            .nth(from)
+            .map_or(0, |line| line.chars().count());
+        let moved = text.slice_delta(start, start + len, PosType::Unicode)?;
+        let old_ids = line_ids(&text, &content);
+        let blocks = self.doc.get_map(BLOCKS);
+        let kinds: Vec<Option<ValueOrContainer>> =
+            old_ids.iter().map(|id| blocks.get(id)).collect();
+
+        // The line and a newline go out and back in, restyled; at most the
+        // moved line and one neighbour are re-terminated and re-keyed
+        let styles: usize = moved
+            .iter()
+            .map(|delta| match delta {
+                TextDelta::Insert {
+                    attributes: Some(attributes),
+                    ..
+                } => attributes.len(),
+                _ => 0,
+            })
+            .sum();
+        let rekeyed = kinds.iter().filter(|kind| kind.is_some()).count().min(2);
+        self.check_op_limit(2 * (len + 1) + MARK_OPS * styles + 2 * rekeyed)?;
+
+        // Take the line out along with one neighbouring newline, then put it
+        // back with a newline on whichever side keeps the line count
+        if from < last {
+            text.delete(start, len + 1)?;
+        } else {
+            text.delete(start - 1, len + 1)?;
+        }
+        let remaining = text.to_string();
+        let newline = TextDelta::Insert {
+            insert: "\n".to_string(),
+            attributes: None,
+        };
+        let mut delta = Vec::new();
+        let pos = if to < last {
+            char_offset(&remaining, to, 0)
+        } else {
+            remaining.chars().count()
+        };
+        if pos > 0 {
+            delta.push(TextDelta::Retain {
+                retain: pos,
+                attributes: None,
+            });
+        }
+        if to == last {
+            delta.push(newline.clone());
+        }
+        delta.extend(moved);
+        if to < last {
+            delta.push(newline);
+        }
+        text.apply_delta(&delta)?;
+
+        // Block types are keyed by line ID, which changes for re-terminated lines
+        let mut order: Vec<usize> = (0..=last).collect();
+        let line = order.remove(from);
+        order.insert(to, line);
+        let new_ids = line_ids(&text, &text.to_string());
+        for (new_id, &old) in new_ids.iter().zip(&order) {
+            if *new_id != old_ids[old] && kinds[old].is_some() {
+                blocks.delete(&old_ids[old])?;
+            }
+        }
+        for (new_id, &old) in new_ids.iter().zip(&order) {
+            if *new_id != old_ids[old] {
+                if let Some(ValueOrContainer::Value(kind)) = &kinds[old] {
+                    blocks.insert(new_id, kind.clone())?;
+                }
+            }
+        }
+
+        self.commit_as(None);
+        Ok(affected)
+    }
+
+    /// The frontier of the currently visible state
+    pub fn frontier_ids(&self) -> Vec<FrontierId> {
+        self.doc
+            .state_frontiers()
+            .iter()
+            .map(|id| FrontierId {
+                peer: id.peer.to_string(),
+                counter: id.counter,
+            })
+            .collect()
+    }
+
+    /// Causal order of two frontiers, or `None` if they are concurrent
+    pub fn compare(
+        &self,
+        a: &[FrontierId],
+        b: &[FrontierId],
+    ) -> Result<Option<Ordering>, KernError> {
+        let frontiers = |ids: &[FrontierId]| -> Result<Frontiers, KernError> {
+            ids.iter()
+                .map(|id| {
+                    let peer = id
+                        .peer
+                        .parse()
+                        .map_err(|_| KernError::InvalidPeerId(id.peer.clone()))?;
+                    Ok(ID::new(peer, id.counter))
+                })
+                .collect()
+        };
+        self.doc
+            .cmp_frontiers(&frontiers(a)?, &frontiers(b)?)
+            .map_err(|_| KernError::UnknownFrontier)
+    }
+
+    /// Ops after encoded frontier `from` up to `to`, in causal order
+    ///
+    /// Meant for debugging sync, not for hot paths: it decodes the whole
+    /// range. Fails with `KernError::OutOfRange` unless `from` is at or
+    /// before `to`.
+    pub fn ops_between(&self, from: &[u8], to: &[u8]) -> Result<Vec<OpRecord>, KernError> {
+        let from = Frontiers::decode(from)?;
+        let to = Frontiers::decode(to)?;
+        match self.doc.cmp_frontiers(&from, &to) {
+            Ok(Some(Ordering::Less | Ordering::Equal)) => {}
+            Ok(_) => {
+                return Err(KernError::OutOfRange(
+                    "`from` is not before `to`".to_string(),
+                ))
+            }
+            Err(_) => return Err(KernError::UnknownFrontier),
+        }
+        let vv = |frontiers: &Frontiers| {
+            self.doc
+                .frontiers_to_vv(frontiers)
+                .ok_or(KernError::UnknownFrontier)
+        };
+        let mut changes = self
+            .doc
+            .export_json_updates_without_peer_compression(&vv(&from)?, &vv(&to)?)
+            .changes;
+        changes.sort_by_key(|change| (change.lamport, change.id.peer));
+
+        let mut ops = Vec::new();
+        for change in changes {
+            for op in change.ops {
+                let (kind, pos, text, len) = match op.content {
+                    JsonOpContent::Text(JsonTextOp::Insert { pos, text }) => {
+                        ("insert", Some(pos as usize), Some(text), None)
+                    }
+                    JsonOpContent::Text(JsonTextOp::Delete { pos, len, .. }) => {
+                        // A negative length deletes backwards from `pos`
+                        let start = if len < 0 { pos + len } else { pos };
+                        let len = Some(len.unsigned_abs() as usize);
+                        ("delete", Some(start.max(0) as usize), None, len)
+                    }
+                    JsonOpContent::Text(JsonTextOp::Mark {
+                        start, style_key, ..
+                    }) => ("mark", Some(start as usize), Some(style_key), None),
+                    JsonOpContent::Text(JsonTextOp::MarkEnd) => ("mark_end", None, None, None),
+                    JsonOpContent::Map(JsonMapOp::Insert { key, .. }) => {
+                        ("set", None, Some(key), None)
+                    }
+                    JsonOpContent::Map(JsonMapOp::Delete { key }) => {
+                        ("remove", None, Some(key), None)
+                    }
+                    _ => ("other", None, None, None),
+                };
+                ops.push(OpRecord {
+                    peer: change.id.peer.to_string(),
+                    counter: op.counter,
+                    container: op.container.to_string(),
+                    kind: kind.to_string(),
+                    pos,
+                    text,
+                    len,
+                });
+            }
+        }
+        Ok(ops)
+    }
+
+    /// Cap the size of the document and its history
+    ///
+    /// Every local mutation checks the caps before touching the document,
+    /// except undo and redo, whose ops Loro only knows once it has made them.
+    /// Imports are judged by their header without being applied, counting
+    /// every new op as a possible character.
+    /// Content already past a new cap is kept; only later edits are refused.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Fail with `KernError::LimitExceeded` if the text would end up `chars`
+    /// long, or the oplog would grow by `added_ops`, beyond the limits
+    fn check_limits(&self, chars: usize, added_ops: usize) -> Result<(), KernError> {
+        if let Some(max) = self.limits.max_chars {
+            if chars > max {
+                return Err(KernError::LimitExceeded(format!(
+                    "{chars} characters, limit {max}"
+                )));
+            }
+        }
+        self.check_op_limit(added_ops)
+    }
+
+    /// The op half of `check_limits`, for changes that leave the text's
+    /// length alone
+    fn check_op_limit(&self, added_ops: usize) -> Result<(), KernError> {
+        if added_ops == 0 {
+            return Ok(());
+        }
+        // Plus the version clock and revision count written with every commit
+        self.check_bare_op_limit(added_ops + 2)
+    }
+
+    /// `check_op_limit` for changes committed without the version clock and
+    /// revision count, like checkpoints and imports
+    fn check_bare_op_limit(&self, added_ops: usize) -> Result<(), KernError> {
+        if let Some(max) = self.limits.max_ops {
+            let ops = self.doc.len_ops() + added_ops;
+            if added_ops > 0 && ops > max {
+                return Err(KernError::LimitExceeded(format!("{ops} ops, limit {max}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch commit mode, first committing anything the old mode held back
+    pub fn set_mode(&mut self, mode: CommitMode) {
+        self.flush();
+        self.commit_mode = mode;
+    }
+
+    /// Commit pending ops, using `message` as the commit message when given
+    ///
+    /// Inside a batch the ops stay pending and only the message is recorded.
+    fn commit_as(&mut self, message: Option<&str>) {
+        self.version += 1;
+        let hold = match self.commit_mode {
+            _ if self.batching => true,
+            CommitMode::Immediate => false,
+            CommitMode::Debounced { ops } => {
+                self.held += 1;
+                self.held < ops
+            }
+        };
+        if hold {
+            // Ride along with whichever commit takes the held ops
+            self.persist_version();
+            if let Some(message) = message {
+                self.doc.set_next_commit_message(message);
+            }
+            return;
+        }
+        self.commit_pending(message);
+    }
+
+    /// Commit whatever is pending as one revision
+    fn commit_pending(&mut self, message: Option<&str>) {
+        self.held = 0;
+        if self.doc.get_pending_txn_len() == 0 {
+            return;
+        }
+        self.revisions += 1;
+        self.persist_version();
+        match message {
+            Some(message) => self
+                .doc
+                .commit_with(CommitOptions::new().commit_msg(message)),
+            None => self.doc.commit(),
+        }
+    }
+
+    /// Record the version clock and revision count in the document so they
+    /// survive reloads
+    ///
+    /// The writes ride along with whatever commit comes next.
+    fn persist_version(&self) {
+        if !self.doc.is_detached() {
+            // Only fails while detached
+            let meta = self.doc.get_map(META);
+            let _ = meta.insert(VERSION_KEY, self.version as i64);
+            let key = format!("{REVISIONS_KEY}:{}", self.doc.peer_id());
+            let _ = meta.insert(&key, self.revisions as i64);
+            // Documents seeded empty record their creation with the first edit
+            if self.created_at().is_none() {
+                let _ = meta.insert(CREATED_AT_KEY, now_secs());
+            }
+        }
+    }
+
+    /// Persist the version clock after an undo or redo, outside the undo
+    /// stack, counting it as a revision
+    fn persist_version_meta(&mut self) {
+        self.revisions += 1;
+        self.persist_version();
+        self.doc
+            .commit_with(CommitOptions::new().origin(META_ORIGIN));
+    }
+
+    /// Attribute each line to the newest insert among its characters
+    ///
+    /// Deletions leave nothing behind to attribute, so a line only shows the
+    /// peer who last inserted into it. Empty lines belong to the peer that
+    /// created their line break.
+    pub fn blame_lines(&self) -> Vec<LineBlame> {
+        let text = self.text();
+        let content = text.to_string();
+        let mut blame = Vec::new();
+        let mut pos = 0;
+        for (line, chars) in content.split('\n').map(|l| l.chars().count()).enumerate() {
+            let span = if chars > 0 {
+                pos..pos + chars
+            } else if pos > 0 {
+                pos - 1..pos
+            } else {
+                pos..pos + 1
+            };
+            let newest = span
+                .filter_map(|p| text.get_cursor(p, Side::Middle)?.id)
+                .filter_map(|id| {
+                    let change = self.doc.get_change(id)?;
+                    let lamport = change.lamport + (id.counter - change.id.counter) as u32;
+                    Some((lamport, id.peer, change.timestamp))
+                })
+                .max();
+            if let Some((_, peer, timestamp)) = newest {
+                blame.push(LineBlame {
+                    line,
+                    peer: peer.to_string(),
+                    timestamp,
+                });
+            }
+            pos += chars + 1;
+        }
+        blame
+    }
+
+    /// Lines where two or more peers inserted text concurrently since the
+    /// encoded frontier `since`, typically the frontier before an import
+    ///
+    /// A heuristic: Loro never fails a merge, and keeps a run typed at one
+    /// spot together, but concurrent edits to the same line can still read
+    /// as a garbled sentence. Insertions where one peer had already seen
+    /// the other's text are sequential and not reported.
+    pub fn find_interleavings(&self, since: &[u8]) -> Result<Vec<Interleaving>, KernError> {
+        let since = Frontiers::decode(since)?;
+        let since = self
+            .doc
+            .frontiers_to_vv(&since)
+            .ok_or(KernError::UnknownFrontier)?;
+
+        let text = self.text();
+        let content = text.to_string();
+        let mut found = Vec::new();
+        let mut pos = 0;
+        for (line, chars) in content.split('\n').map(|l| l.chars().count()).enumerate() {
+            // Newest character each peer inserted into this line since `since`
+            let mut newest: BTreeMap<u64, ID> = BTreeMap::new();
+            for id in (pos..pos + chars).filter_map(|p| text.get_cursor(p, Side::Middle)?.id) {
+                if id.counter >= since.get(&id.peer).copied().unwrap_or(0) {
+                    let entry = newest.entry(id.peer).or_insert(id);
+                    entry.counter = entry.counter.max(id.counter);
+                }
+            }
+            let ids: Vec<ID> = newest.into_values().collect();
+            let concurrent = ids.iter().enumerate().any(|(i, a)| {
+                ids[i + 1..].iter().any(|b| {
+                    let order = self
+                        .doc
+                        .cmp_frontiers(&Frontiers::from_id(*a), &Frontiers::from_id(*b));
+                    matches!(order, Ok(None))
+                })
+            });
+            if concurrent {
+                let mut peers: Vec<String> = ids.iter().map(|id| id.peer.to_string()).collect();
+                peers.sort();
+                found.push(Interleaving { line, peers });
+            }
+            pos += chars + 1;
+        }
+        Ok(found)
+    }
+
+    /// Changes in the document's history, newest first
+    ///
+    /// Consecutive local commits without a message may be merged by Loro into
+    /// a single change.
+    pub fn history(&self) -> Vec<ChangeRecord> {
+        let heads: Vec<ID> = self.doc.oplog_frontiers().iter().collect();
+        let mut changes = Vec::new();
+        // Only fails for heads missing from the oplog, which ours can't be
+        let _ = self.doc.travel_change_ancestors(&heads, &mut |change| {
+            changes.push(change);
+            ControlFlow::Continue(())
+        });
+        changes.sort_by(|a, b| b.lamport.cmp(&a.lamport).then(b.id.cmp(&a.id)));
+        changes
+            .into_iter()
+            .map(|change| ChangeRecord {
+                message: change.message.map(|m| m.to_string()),
+                peer: change.id.peer.to_string(),
+                timestamp: change.timestamp,
+            })
+            .collect()
+    }
+
+    /// Build the current document view, including per-line formatting
+    pub fn view(&self) -> DocumentView {
+        let view = self.view_range(0, usize::MAX);
+        let mut served = self.served_views.borrow_mut();
+        if served
+            .back()
+            .is_none_or(|(version, _)| *version != view.version)
+        {
+            if served.len() == SERVED_VIEWS {
+                served.pop_front();
+            }
+            served.push_back((view.version, view.lines.clone()));
+        }
+        view
+    }
+
+    /// Short hash of the visible text, marks and block types
+    ///
+    /// Depends only on what the document shows, not the history that led
+    /// there, so converged peers can compare hashes instead of content.
+    pub fn content_hash(&self) -> Result<String, KernError> {
+        let mut canonical = Vec::new();
+        for line in self.view_range(0, usize::MAX).lines {
+            let mut marks = line.marks.unwrap_or_default();
+            marks.sort_by(|a, b| (a.start, a.end, &a.key).cmp(&(b.start, b.end, &b.key)));
+            // Everything but `id`, an op ID that depends on history
+            serde_json::to_writer(&mut canonical, &(line.content, line.block, marks))
+                .map_err(|e| KernError::Serialization(e.to_string()))?;
+            canonical.push(b'\n');
+        }
+        Ok(format!("{:032x}", xxhash_rust::xxh3::xxh3_128(&canonical)))
+    }
+
+    /// Changes from the view served at `since_version` to the current one
+    ///
+    /// Only the last `SERVED_VIEWS` distinct views handed out by `view` can be
+    /// diffed against; older or unknown versions get a full view.
+    pub fn view_patch(&self, since_version: u64) -> ViewPatch {
+        let view = self.view();
+        let served = self.served_views.borrow();
+        match served.iter().find(|(version, _)| *version == since_version) {
+            Some((_, old)) => ViewPatch {
+                version: view.version,
+                full: false,
+                ops: line_ops(old, &view.lines),
+                lines: None,
+            },
+            None => ViewPatch {
+                version: view.version,
+                full: true,
+                ops: Vec::new(),
+                lines: Some(view.lines),
+            },
+        }
+    }
+
+    /// Build the view of lines `[start, end)`, clamped to the document bounds
+    pub fn view_range(&self, start: usize, end: usize) -> DocumentView {
+        let mut cache = self.view_cache.borrow_mut();
+        if cache.as_ref().is_none_or(|c| c.version != self.version) {
+            *cache = Some(ViewCache {
+                version: self.version,
+                lines: self.build_lines(),
+            });
+        }
+        let all = cache.as_ref().map_or(&[][..], |c| &c.lines[..]);
+        let total_lines = all.len();
+        let end = end.min(total_lines);
+        let start = start.min(end);
+
+        DocumentView {
+            lines: all[start..end].to_vec(),
+            version: self.version,
+            total_lines,
+        }
+    }
+
+    /// Split the engine's container into view lines from scratch
+    fn build_lines(&self) -> Vec<LineView> {
+        let text = self.text();
+        let content = text.to_string();
+        let mut marks = line_marks(&text);
+        let ids = line_ids(&text, &content);
+        let blocks = self.doc.get_map(BLOCKS);
+
+        content
+            .split('\n')
+            .zip(ids)
+            .enumerate()
+            .map(|(i, (line, id))| {
+                let marks = marks
+                    .get_mut(i)
+                    .filter(|spans| !spans.is_empty())
+                    .map(std::mem::take);
+                let block = match blocks.get(&id) {
+                    Some(ValueOrContainer::Value(LoroValue::String(kind))) => kind.to_string(),
+                    _ => default_block(),
+                };
+                LineView {
+                    id,
+                    content: line.to_string(),
+                    char_len: line.chars().count(),
+                    styled: marks.is_some(),
+                    marks,
+                    block,
+                    visual_rows: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Resolve an encoded cursor to its current position
+    pub fn resolve_cursor(&self, cursor: &[u8]) -> Result<Position, KernError> {
+        let cursor = Cursor::decode(cursor).map_err(|e| KernError::InvalidCursor(e.to_string()))?;
+        let pos = self
+            .doc
+            .get_cursor_pos(&cursor)
+            .map_err(|e| KernError::InvalidCursor(e.to_string()))?
+            .current
+            .pos;
+        Ok(line_col(&self.get_text(), pos))
+    }
+
+    /// Set this peer's presence state
+    pub fn set_local_presence(&mut self, state: impl Into<LoroValue>) {
+        self.awareness.set(&self.get_peer_id(), state);
+    }
+
+    /// Merge a remote awareness update, pruning stale peers
+    pub fn merge_awareness(&mut self, data: &[u8]) -> Result<Vec<PeerState>, KernError> {
+        self.awareness
+            .apply(data)
+            .map_err(|e| KernError::InvalidAwareness(e.to_string()))?;
+        self.awareness.remove_outdated();
+        Ok(self.peer_states())
+    }
+
+    /// Set this peer's selection from two encoded cursors
+    ///
+    /// Cursors track the text they're anchored to, so peers see the selection
+    /// move with later edits. An empty selection is shared as a single caret.
+    pub fn set_local_selection(&mut self, anchor: &[u8], head: &[u8]) -> Result<(), KernError> {
+        for cursor in [anchor, head] {
+            Cursor::decode(cursor).map_err(|e| KernError::InvalidCursor(e.to_string()))?;
+        }
+        let mut ends = vec![LoroValue::from(anchor.to_vec())];
+        if anchor != head {
+            ends.push(head.to_vec().into());
+        }
+        let key = format!("{SELECTION_KEY}{}", self.get_peer_id());
+        self.awareness.set(&key, ends);
+        Ok(())
+    }
+
+    /// Attach a comment to the range between two encoded cursors, returning
+    /// its ID
+    ///
+    /// Comments are synced with the document but kept out of its text. The
+    /// cursors track the range through edits and merges.
+    pub fn add_comment(
+        &mut self,
+        anchor: &[u8],
+        head: &[u8],
+        text: &str,
+    ) -> Result<String, KernError> {
+        self.ensure_editable()?;
+        for cursor in [anchor, head] {
+            Cursor::decode(cursor).map_err(|e| KernError::InvalidCursor(e.to_string()))?;
+        }
+
+        // The ID of the next op is unique across peers
+        let peer = self.doc.peer_id();
+        let counter = self.doc.oplog_vv().get(&peer).copied().unwrap_or(0) as usize
+            + self.doc.get_pending_txn_len();
+        let id = format!("{counter}@{peer}");
+        // The comment map and its four fields
+        self.check_op_limit(5)?;
+        let comment = self
+            .doc
+            .get_map(COMMENTS)
+            .insert_container(&id, LoroMap::new())?;
+        comment.insert("anchor", anchor.to_vec())?;
+        comment.insert("head", head.to_vec())?;
+        comment.insert("text", text)?;
+        comment.insert("resolved", false)?;
+        self.commit_as(None);
+        Ok(id)
+    }
+
+    /// All comments with their current positions, in document order
+    ///
+    /// Comments whose anchors refer to edits not yet received are skipped.
+    pub fn comments(&self) -> Vec<Comment> {
+        let LoroValue::Map(all) = self.doc.get_map(COMMENTS).get_deep_value() else {
+            return Vec::new();
+        };
+        let resolve = |end: Option<&LoroValue>| match end {
+            Some(LoroValue::Binary(cursor)) => self.resolve_cursor(cursor).ok(),
+            _ => None,
+        };
+        let mut comments: Vec<Comment> = all
+            .iter()
+            .filter_map(|(id, fields)| {
+                let LoroValue::Map(fields) = fields else {
+                    return None;
+                };
+                Some(Comment {
+                    id: id.clone(),
+                    text: match fields.get("text") {
+                        Some(LoroValue::String(text)) => text.to_string(),
+                        _ => String::new(),
+                    },
+                    resolved: matches!(fields.get("resolved"), Some(LoroValue::Bool(true))),
+                    anchor_pos: resolve(fields.get("anchor"))?,
+                    head_pos: resolve(fields.get("head"))?,
+                })
+            })
+            .collect();
+        comments.sort_by(|a, b| {
+            let start = |c: &Comment| (c.anchor_pos.line, c.anchor_pos.col);
+            start(a).cmp(&start(b)).then_with(|| a.id.cmp(&b.id))
+        });
+        comments
+    }
+
+    /// Mark a comment as resolved, failing with `KernError::UnknownComment`
+    /// if there's no comment `id`
+    pub fn resolve_comment(&mut self, id: &str) -> Result<(), KernError> {
+        self.ensure_editable()?;
+        let Some(ValueOrContainer::Container(Container::Map(comment))) =
+            self.doc.get_map(COMMENTS).get(id)
+        else {
+            return Err(KernError::UnknownComment(id.to_string()));
+        };
+        self.check_op_limit(1)?;
+        comment.insert("resolved", true)?;
+        self.commit_as(None);
+        Ok(())
+    }
+
+    /// All live selections, ordered by peer ID
+    ///
+    /// Selections anchored to edits this document hasn't seen yet are skipped.
+    pub fn peer_selections(&self) -> Vec<PeerSelection> {
+        let resolve = |end: &LoroValue| match end {
+            LoroValue::Binary(cursor) => self.resolve_cursor(cursor).ok(),
+            _ => None,
+        };
+        let mut selections: Vec<PeerSelection> = self
+            .awareness
+            .get_all_states()
+            .into_iter()
+            .filter_map(|(key, ends)| {
+                let peer = key.strip_prefix(SELECTION_KEY)?.to_string();
+                let LoroValue::List(ends) = ends else {
+                    return None;
+                };
+                Some(PeerSelection {
+                    peer,
+                    anchor_pos: resolve(ends.first()?)?,
+                    head_pos: resolve(ends.last()?)?,
+                })
+            })
+            .collect();
+        selections.sort_by(|a, b| a.peer.cmp(&b.peer));
+        selections
+    }
+
+    /// All live presence states, ordered by peer ID
+    pub fn peer_states(&self) -> Vec<PeerState> {
+        let mut states: Vec<PeerState> = self
+            .awareness
+            .get_all_states()
+            .into_iter()
+            .filter(|(key, _)| !key.starts_with(SELECTION_KEY))
+            .map(|(peer, state)| PeerState { peer, state })
+            .collect();
+        states.sort_by(|a, b| a.peer.cmp(&b.peer));
+        states
+    }
+
+    /// Find all non-overlapping matches of `query` in document order
+    ///
+    /// Matches never span lines; `col` and `length` count Unicode scalars.
+    pub fn find(&self, query: &str, case_sensitive: bool) -> Vec<SearchMatch> {
+        let needle: Vec<char> = query.chars().collect();
+        if needle.is_empty() {
+            return Vec::new();
+        }
+
+        let same =
+            |a: char, b: char| a == b || (!case_sensitive && a.to_lowercase().eq(b.to_lowercase()));
+
+        let mut matches = Vec::new();
+        for (line, content) in self.get_text().split('\n').enumerate() {
+            let hay: Vec<char> = content.chars().collect();
+            let mut col = 0;
+            while col + needle.len() <= hay.len() {
+                if hay[col..col + needle.len()]
+                    .iter()
+                    .zip(&needle)
+                    .all(|(&a, &b)| same(a, b))
+                {
+                    matches.push(SearchMatch {
+                        line,
+                        col,
+                        length: needle.len(),
+                    });
+                    col += needle.len();
+                } else {
+                    col += 1;
+                }
+            }
+        }
+        matches
+    }
+
+    /// Apply a formatting mark over the character range `[start, end)`
+    pub fn mark_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        key: &str,
+        value: impl Into<LoroValue>,
+    ) -> Result<(), KernError> {
+        self.ensure_editable()?;
+        self.ensure_rich()?;
+        self.check_op_limit(MARK_OPS)?;
+        self.text().mark(start..end, key, value)?;
+        self.commit_as(None);
+        Ok(())
+    }
+
+    /// Marks on the character before the caret, which is what typing there
+    /// would inherit; at the start of a line, the character after it
+    pub fn marks_at_caret(
+        &self,
+        line: usize,
+        col: usize,
+    ) -> Result<BTreeMap<String, LoroValue>, KernError> {
+        let text = self.text();
+        let content = text.to_string();
+        check_line(&content, line)?;
+        let pos = char_offset(&content, line, col);
+        let at = if pos > 0 && col > 0 { pos - 1 } else { pos };
+        if at >= text.len_unicode() {
+            return Ok(BTreeMap::new());
+        }
+
+        let mut marks = BTreeMap::new();
+        for delta in text.slice_delta(at, at + 1, PosType::Unicode)? {
+            if let TextDelta::Insert {
+                attributes: Some(attributes),
+                ..
+            } = delta
+            {
+                marks.extend(attributes.into_iter().filter(|(_, value)| is_set(value)));
+            }
+        }
+        Ok(marks)
+    }
+
+    /// Export a full snapshot as standard base64, for text-only transports
+    pub fn export_snapshot_base64(&self) -> Result<String, KernError> {
+        Ok(base64::engine::general_purpose::STANDARD.encode(self.export_snapshot()?))
+    }
+
+    /// Import a base64 blob from `export_snapshot_base64`
+    ///
+    /// Malformed base64 fails with `KernError::InvalidBase64` before anything
+    /// is imported.
+    pub fn import_base64(&mut self, data: &str) -> Result<LineRange, KernError> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data.trim())
+            .map_err(|e| KernError::InvalidBase64(e.to_string()))?;
+        self.import_bytes(&bytes)
+    }
+
+    /// Import a compressed or raw blob, returning the range of changed lines
+    pub fn import_compressed(&mut self, data: &[u8]) -> Result<LineRange, KernError> {
+        match data.split_first() {
+            Some((&COMPRESSED_MAGIC, deflated)) => {
+                let snapshot = miniz_oxide::inflate::decompress_to_vec(deflated)
+                    .map_err(|e| KernError::Decompress(e.to_string()))?;
+                self.import_bytes(&snapshot)
+            }
+            _ => self.import_bytes(data),
+        }
+    }
+
+    /// Encoded frontier saved under checkpoint `name`
+    fn checkpoint_frontier(&self, name: &str) -> Result<Vec<u8>, KernError> {
+        match self.doc.get_map(CHECKPOINTS).get(name) {
+            Some(ValueOrContainer::Value(LoroValue::Binary(frontier))) => Ok(frontier.to_vec()),
+            _ => Err(KernError::UnknownCheckpoint(name.to_string())),
+        }
+    }
+
+    /// The op that precedes the latest `depth` ops, walking back in causal order
+    fn frontier_before_last_ops(&self, depth: usize) -> Option<ID> {
+        let heads: Vec<ID> = self.doc.oplog_frontiers().iter().collect();
+        let mut remaining = depth;
+        let mut found = None;
+        self.doc
+            .travel_change_ancestors(&heads, &mut |change| {
+                if change.len > remaining {
+                    let counter = change.id.counter + (change.len - remaining) as i32 - 1;
+                    found = Some(ID::new(change.id.peer, counter));
+                    ControlFlow::Break(())
+                } else {
+                    remaining -= change.len;
+                    ControlFlow::Continue(())
+                }
+            })
+            .ok()?;
+        found
+    }
+
+    /// Register a Rust callback receiving changed line indices on each change
+    pub fn subscribe_lines<F>(&mut self, callback: F)
+    where
+        F: Fn(&[usize]) + Send + Sync + 'static,
+    {
+        let text = self.text();
+        let last = Mutex::new(text.to_string());
+        let id = text.id();
+        let subscription = self.doc.subscribe(
+            &id,
+            Arc::new(move |_| {
+                let current = text.to_string();
+                let mut last = last.lock().unwrap();
+                let lines = changed_lines(&last, &current);
+                *last = current;
+                callback(&lines);
+            }),
+        );
+        self.subscription = Some(subscription);
+    }
+
+    /// Register a Rust callback receiving the character-level delta of each
+    /// local or remote change to the text
+    ///
+    /// Formatting-only changes arrive as bare retains. Kept apart from the
+    /// line subscription, so both can be active.
+    pub fn subscribe_deltas<F>(&mut self, callback: F)
+    where
+        F: Fn(&[DeltaOp]) + Send + Sync + 'static,
+    {
+        let id = self.text().id();
+        let subscription = self.doc.subscribe(
+            &id,
+            Arc::new(move |event: DiffEvent| {
+                for container in &event.events {
+                    let Diff::Text(deltas) = &container.diff else {
+                        continue;
+                    };
+                    let delta: Vec<DeltaOp> = deltas
+                        .iter()
+                        .map(|delta| match delta {
+                            TextDelta::Retain { retain, .. } => DeltaOp::Retain(*retain),
+                            TextDelta::Insert { insert, .. } => DeltaOp::Insert(insert.clone()),
+                            TextDelta::Delete { delete } => DeltaOp::Delete(*delete),
+                        })
+                        .collect();
+                    callback(&delta);
+                }
+            }),
+        );
+        self.delta_subscription = Some(subscription);
+    }
+
+    /// Register a Rust callback receiving every line changed since its last
+    /// call, coalescing change events
+    ///
+    /// The callback fires as soon as `max_lag_ops` ops have accumulated.
+    /// Otherwise the first event after a call hands a flush task to
+    /// `schedule`, which should run it soon, e.g. in the next microtask.
+    /// Tasks still pending after `unsubscribe` do nothing.
+    pub fn subscribe_lines_batched<F, S>(&mut self, max_lag_ops: usize, callback: F, schedule: S)
+    where
+        F: Fn(&[usize]) + Send + Sync + 'static,
+        S: Fn(FlushTask) + Send + Sync + 'static,
+    {
+        let text = self.text();
+        let batch = Arc::new(LineBatch {
+            text: text.clone(),
+            state: Mutex::new((text.to_string(), 0, false)),
+            callback: Box::new(callback),
+        });
+        let id = text.id();
+        let subscription = self.doc.subscribe(
+            &id,
+            Arc::new(move |event: DiffEvent| {
+                let ops = event_ops(&event);
+                let mut state = batch.state.lock().unwrap();
+                state.1 += ops;
+                if state.1 >= max_lag_ops {
+                    drop(state);
+                    batch.flush();
+                } else if !state.2 {
+                    state.2 = true;
+                    let pending = Arc::downgrade(&batch);
+                    schedule(Box::new(move || {
+                        if let Some(batch) = pending.upgrade() {
+                            batch.flush();
+                        }
+                    }));
+                }
+            }),
+        );
+        self.subscription = Some(subscription);
+    }
+
+    /// Import saved bytes, returning the range of lines that differ afterwards
+    pub fn import_bytes(&mut self, data: &[u8]) -> Result<LineRange, KernError> {
+        Ok(self.import_applying(data)?.0)
+    }
+
+    /// Import `data`, summarizing which of its ops Loro applied or buffered
+    pub fn import_report(&mut self, data: &[u8]) -> Result<ImportReport, KernError> {
+        let (_, status) = self.import_applying(data)?;
+        let count = |range: &VersionRange| -> usize {
+            range
+                .iter()
+                .map(|(_, (start, end))| (end - start) as usize)
+                .sum()
+        };
+        let pending_count = status.pending.as_ref().map_or(0, count);
+        Ok(ImportReport {
+            applied_count: count(&status.success),
+            pending_count,
+            success: pending_count == 0,
+        })
+    }
+
+    /// Import saved bytes, measuring its size, ops applied and duration
+    ///
+    /// Opt-in: plain `import_bytes` reads no clock.
+    pub fn import_timed(&mut self, data: &[u8]) -> Result<ImportMetrics, KernError> {
+        let stopwatch = Stopwatch::start();
+        let report = self.import_report(data)?;
+        Ok(ImportMetrics {
+            bytes: data.len(),
+            applied_ops: report.applied_count,
+            duration_ms: stopwatch.elapsed_ms(),
+        })
+    }
+
+    fn import_applying(&mut self, data: &[u8]) -> Result<(LineRange, ImportStatus), KernError> {
+        let payload = open_blob(data)?;
+        if self.limits != Limits::default() {
+            // Bound the import by its header so an oversized one never lands,
+            // assuming every new op may insert a character
+            let added = new_blob_ops(&payload, &self.doc.oplog_vv())?;
+            if added > 0 {
+                self.check_limits(self.text().len_unicode() + added, 0)?;
+                self.check_bare_op_limit(added)?;
+            }
+        }
+        let text = self.text();
+        let before = text.to_string();
+        let heads = self.doc.oplog_frontiers();
+        let status = self.doc.import(&payload)?;
+        for (&peer, &(start, _)) in status.pending.iter().flat_map(|range| range.iter()) {
+            let lowest = self.awaiting.entry(peer).or_insert(start);
+            *lowest = (*lowest).min(start);
+        }
+        let vv = self.doc.oplog_vv();
+        // Applied once the peer's counter has moved past them
+        self.awaiting
+            .retain(|peer, start| vv.get(peer).copied().unwrap_or(0) <= *start);
+        if self.doc.oplog_frontiers() == heads {
+            // Nothing new, or only ops still waiting on a predecessor
+            return Ok((LineRange::default(), status));
+        }
+        // Never fall behind a clock that was saved or advanced elsewhere
+        self.version = (self.version + 1).max(stored_version(&self.doc));
+        self.revisions = self.revisions.max(stored_revisions(&self.doc));
+        let after = text.to_string();
+        if let Some(callback) = &self.sync_callback {
+            callback(self.version, &changed_lines(&before, &after));
+        }
+        Ok((LineRange::between(&before, &after), status))
+    }
+
+    /// Import `data` into a throwaway fork and report the resulting text
+    pub fn preview(&self, data: &[u8]) -> Result<ImportPreview, KernError> {
+        let before = self.get_text();
+        let scratch = self.doc.fork();
+        scratch.import(&open_blob(data)?)?;
+        let text = scratch.get_text(&*self.container).to_string();
+        Ok(ImportPreview {
+            changed_lines: changed_lines(&before, &text),
+            text,
+        })
+    }
+
+    /// Register a Rust callback fired once per import that brought new ops
+    pub fn on_sync_with<F>(&mut self, callback: F)
+    where
+        F: Fn(u64, &[usize]) + 'static,
+    {
+        self.sync_callback = Some(Box::new(callback));
+    }
+}
+
+/// One step of an edit script turning `a` into `b`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Shortest edit script from `a` to `b` using Myers' O((N+M)D) algorithm
+fn diff_ops<T: PartialEq>(a: &[T], b: &[T]) -> Vec<DiffOp> {
+    // Common prefix/suffix are cheap to strip and keep the trace small
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let offset = n + m;
+    let mut v = vec![0isize; 2 * (n + m) as usize + 2];
+    let mut trace = Vec::new();
+    let at = |k: isize| (k + offset) as usize;
+    // Whether step `d` on diagonal `k` comes from `k + 1` (an insertion)
+    let down = |v: &[isize], d: isize, k: isize| k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]);
+
+    let mut found = false;
+    'search: for d in 0..=n + m {
+        if (d as usize + 1) * v.len() > DIFF_BUDGET {
+            break;
+        }
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if down(&v, d, k) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'search;
+            }
+        }
+    }
+    if !found {
+        // Too far apart to diff within budget: replace the middle wholesale
+        let mut ops = vec![DiffOp::Equal; prefix];
+        ops.extend(std::iter::repeat_n(DiffOp::Delete, n as usize));
+        ops.extend(std::iter::repeat_n(DiffOp::Insert, m as usize));
+        ops.extend(std::iter::repeat_n(DiffOp::Equal, suffix));
+        return ops;
+    }
+
+    let mut ops = vec![DiffOp::Equal; suffix];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if down(v, d, k) { k + 1 } else { k - 1 };
+        let prev_x = v[at(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(DiffOp::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == prev_x {
+                DiffOp::Insert
+            } else {
+                DiffOp::Delete
+            });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.extend(std::iter::repeat_n(DiffOp::Equal, prefix));
+    ops.reverse();
+    ops
+}
+
+/// The splices turning `old` into `new` with as few operations as possible,
+/// each positioned against the text as left by the ones before it
+///
+/// Lines are diffed first so the character-level pass only runs over the
+/// changed hunks, keeping the quadratic worst case local.
+fn splice_diff(old: &str, new: &str) -> Vec<Splice> {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let (mut i, mut j, mut pos) = (0, 0, 0);
+    let mut splices = Vec::new();
+    let mut ops = diff_ops(&old_lines, &new_lines).into_iter().peekable();
+    while let Some(op) = ops.next() {
+        if op == DiffOp::Equal {
+            pos += old_lines[i].chars().count();
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (start_i, start_j) = (i, j);
+        let mut op = op;
+        loop {
+            match op {
+                DiffOp::Delete => i += 1,
+                _ => j += 1,
+            }
+            match ops.next_if(|op| *op != DiffOp::Equal) {
+                Some(next) => op = next,
+                None => break,
+            }
+        }
+        let removed: Vec<char> = old_lines[start_i..i].concat().chars().collect();
+        let added: Vec<char> = new_lines[start_j..j].concat().chars().collect();
+        pos = splice_chars(&mut splices, pos, &removed, &added);
+    }
+    splices
+}
+
+/// Push the splices of the character diff from `old` to `new` at `pos`,
+/// returning the position just past the spliced region
+fn splice_chars(splices: &mut Vec<Splice>, mut pos: usize, old: &[char], new: &[char]) -> usize {
+    let (mut i, mut j) = (0, 0);
+    let mut ops = diff_ops(old, new).into_iter().peekable();
+    while let Some(op) = ops.next() {
+        match op {
+            DiffOp::Equal => {
+                pos += 1;
+                i += 1;
+                j += 1;
+            }
+            DiffOp::Delete => {
+                let mut count = 1;
+                while ops.next_if_eq(&DiffOp::Delete).is_some() {
+                    count += 1;
+                }
+                splices.push(Splice {
+                    start: pos,
+                    delete: count,
+                    insert: None,
+                });
+                i += count;
+            }
+            DiffOp::Insert => {
+                let mut count = 1;
+                while ops.next_if_eq(&DiffOp::Insert).is_some() {
+                    count += 1;
+                }
+                splices.push(Splice {
+                    start: pos,
+                    delete: 0,
+                    insert: Some(new[j..j + count].iter().collect()),
+                });
+                pos += count;
+                j += count;
+            }
+        }
+    }
+    debug_assert_eq!((i, j), (old.len(), new.len()));
+    pos
+}
+
+/// The runs of lines that differ between `old` and `new`, each as the range
+/// it covers in `new`
+fn line_hunks(old: &str, new: &str) -> Vec<LineRange> {
+    let old_lines: Vec<&str> = old.split('\n').collect();
+    let new_lines: Vec<&str> = new.split('\n').collect();
+    let mut hunks = Vec::new();
+    let (mut removed, mut j) = (0, 0);
+    let mut start = None;
+    for op in diff_ops(&old_lines, &new_lines) {
+        match op {
+            DiffOp::Equal => {
+                if let Some(start) = start.take() {
+                    hunks.push(LineRange {
+                        start,
+                        end: j,
+                        inserted: j - start,
+                        removed,
+                    });
+                }
+                removed = 0;
+                j += 1;
+            }
+            DiffOp::Delete => {
+                start.get_or_insert(j);
+                removed += 1;
+            }
+            DiffOp::Insert => {
+                start.get_or_insert(j);
+                j += 1;
+            }
+        }
+    }
+    if let Some(start) = start {
+        hunks.push(LineRange {
+            start,
+            end: j,
+            inserted: j - start,
+            removed,
+        });
+    }
+    hunks
+}
+
+/// Line-level changelist between two texts
+///
+/// Within each hunk, removed and added lines are paired up as modifications.
+fn line_diff(old: &str, new: &str) -> Vec<LineChange> {
+    // An empty text has no lines to diff, rather than one empty line
+    fn lines(text: &str) -> Vec<&str> {
+        if text.is_empty() {
+            Vec::new()
+        } else {
+            text.split('\n').collect()
+        }
+    }
+    let (old_lines, new_lines) = (lines(old), lines(new));
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let mut flush = |removed: &mut Vec<usize>, added: &mut Vec<usize>| {
+        let paired = removed.len().min(added.len());
+        for &line in &added[..paired] {
+            changes.push(LineChange {
+                line,
+                kind: LineChangeKind::Modified,
+            });
+        }
+        for &line in &removed[paired..] {
+            changes.push(LineChange {
+                line,
+                kind: LineChangeKind::Removed,
+            });
+        }
+        for &line in &added[paired..] {
+            changes.push(LineChange {
+                line,
+                kind: LineChangeKind::Added,
+            });
+        }
+        removed.clear();
+        added.clear();
+    };
+
+    for op in diff_ops(&old_lines, &new_lines) {
+        match op {
+            DiffOp::Equal => {
+                flush(&mut removed, &mut added);
+                i += 1;
+                j += 1;
+            }
+            DiffOp::Delete => {
+                removed.push(i);
+                i += 1;
+            }
+            DiffOp::Insert => {
+                added.push(j);
+                j += 1;
+            }
+        }
+    }
+    flush(&mut removed, &mut added);
+    changes
+}
+
+/// Steps turning the `old` view lines into `new`, pairing removed and added
+/// lines into replacements
+fn line_ops(old: &[LineView], new: &[LineView]) -> Vec<LineOp> {
+    let mut ops = Vec::new();
+    let (mut j, mut removed, mut added) = (0, 0, 0);
+    let mut flush = |j: usize, removed: &mut usize, added: &mut usize| {
+        // The hunk's added lines are new[start..j]
+        let start = j - *added;
+        let paired = (*removed).min(*added);
+        for (index, line) in new.iter().enumerate().take(start + paired).skip(start) {
+            ops.push(LineOp::Replace {
+                index,
+                line: line.clone(),
+            });
+        }
+        for _ in paired..*removed {
+            ops.push(LineOp::Delete {
+                index: start + paired,
+            });
+        }
+        for (index, line) in new.iter().enumerate().take(j).skip(start + paired) {
+            ops.push(LineOp::Insert {
+                index,
+                line: line.clone(),
+            });
+        }
+        *removed = 0;
+        *added = 0;
+    };
+
+    for op in diff_ops(old, new) {
+        match op {
+            DiffOp::Equal => {
+                flush(j, &mut removed, &mut added);
+                j += 1;
+            }
+            DiffOp::Delete => removed += 1,
+            DiffOp::Insert => {
+                added += 1;
+                j += 1;
+            }
+        }
+    }
+    flush(j, &mut removed, &mut added);
+    ops
+}
+
+/// Indices (in `new`) of the lines between the first and last that differ
+fn changed_lines(old: &str, new: &str) -> Vec<usize> {
+    let old: Vec<&str> = old.split('\n').collect();
+    let new: Vec<&str> = new.split('\n').collect();
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    if prefix == old.len() && prefix == new.len() {
+        return Vec::new();
+    }
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let end = new.len() - suffix;
+    if end > prefix {
+        (prefix..end).collect()
+    } else {
+        // Pure deletion: repaint the line that now sits at the cut
+        vec![prefix.min(new.len().saturating_sub(1))]
+    }
+}
+
+/// An edit resolved against the text it applies to, so its size is known
+/// before applying it
+struct Splice {
+    /// Character offset the deletion starts at and the insert goes in at
+    start: usize,
+    /// Characters deleted, after clamping to the start of the document
+    delete: usize,
+    insert: Option<String>,
+}
+
+impl Splice {
+    /// Resolve `edit` against `content`, along with the lines it touches
+    fn plan(content: &str, edit: &EditDelta) -> Result<(Splice, LineRange), KernError> {
+        let edit = resolve_append(content, edit);
+        check_line(content, edit.line)?;
+        let pos = char_offset(content, edit.line, edit.col);
+
+        // Backspace semantics: never reach past the start of the document
+        let delete = edit.delete.unwrap_or(0).min(pos);
+        // The caret moves back over the deleted text, so insert where it then is
+        let start = pos - delete;
+
+        // Deleting past the line start joins the lines before it onto this one
+        let first = line_col(content, start).line;
+        let inserted = 1 + edit.insert.as_ref().map_or(0, |s| s.matches('\n').count());
+        let splice = Splice {
+            start,
+            delete,
+            insert: edit.insert.clone(),
+        };
+        Ok((
+            splice,
+            LineRange {
+                start: first,
+                end: first + inserted,
+                inserted,
+                removed: edit.line - first + 1,
+            },
+        ))
+    }
+
+    fn inserted(&self) -> usize {
+        self.insert.as_ref().map_or(0, |s| s.chars().count())
+    }
+
+    /// Ops the splice adds to the oplog, one per character
+    fn ops(&self) -> usize {
+        self.delete + self.inserted()
+    }
+
+    /// Length of a text `len` characters long once spliced
+    fn len_after(&self, len: usize) -> usize {
+        len - self.delete + self.inserted()
+    }
+
+    /// Whether the splice breaks the last line of a text `len` long at its
+    /// end, where there is no newline of its own to keep its block type
+    fn breaks_end(&self, len: usize) -> bool {
+        self.start + self.delete == len && self.insert.as_ref().is_some_and(|s| s.contains('\n'))
+    }
+
+    /// Apply the splice to `text` without committing
+    ///
+    /// A line broken at its end is broken after its own line ending rather
+    /// than before it, so the newline keying its block type stays with it
+    /// instead of moving to the new line. The text comes out the same.
+    fn apply(&self, text: &LoroText) -> Result<(), KernError> {
+        if self.delete > 0 {
+            text.delete(self.start, self.delete)?;
+        }
+        let Some(insert) = &self.insert else {
+            return Ok(());
+        };
+        let ending = match text.char_at(self.start) {
+            Ok('\n') => Some("\n"),
+            Ok('\r') if text.char_at(self.start + 1).is_ok_and(|c| c == '\n') => Some("\r\n"),
+            _ => None,
+        };
+        let broken = ending.and_then(|ending| {
+            let (line, tail) = insert.split_at(insert.find('\n')? + 1);
+            Some((line.strip_suffix(ending)?, tail, ending))
+        });
+        match broken {
+            Some((head, tail, ending)) => {
+                let after = self.start + ending.chars().count();
+                text.insert(after, &format!("{tail}{ending}"))?;
+                if !head.is_empty() {
+                    text.insert(self.start, head)?;
+                }
+            }
+            None => text.insert(self.start, insert)?,
+        }
+        Ok(())
+    }
+
+    /// Apply the splice to a plain copy of the text
+    fn apply_to(&self, content: &mut String) {
+        let byte = |chars: usize| {
+            content
+                .char_indices()
+                .nth(chars)
+                .map_or(content.len(), |(i, _)| i)
+        };
+        let (start, end) = (byte(self.start), byte(self.start + self.delete));
+        content.replace_range(start..end, self.insert.as_deref().unwrap_or(""));
+    }
+}
+
+/// `edit` moved onto the end of the last line when it targets the line just
+/// past it, which doesn't exist until the edit appends it
+///
+/// That line starts after an implicit line break, so an insert there gets
+/// the break prepended, while a delete removes the break first.
+fn resolve_append<'a>(content: &str, edit: &'a EditDelta) -> Cow<'a, EditDelta> {
+    let line_count = content.split('\n').count();
+    if edit.line != line_count {
+        return Cow::Borrowed(edit);
+    }
+    let delete = edit.delete.unwrap_or(0);
+    let insert = match &edit.insert {
+        Some(text) if delete == 0 => Some(format!("\n{text}")),
+        insert => insert.clone(),
+    };
+    Cow::Owned(EditDelta {
+        line: line_count - 1,
+        col: usize::MAX,
+        insert,
+        delete: Some(delete.saturating_sub(1)).filter(|&d| d > 0),
+    })
+}
+
+/// Fail with `KernError::OutOfRange` unless `line` exists in `content`
+fn check_line(content: &str, line: usize) -> Result<(), KernError> {
+    let line_count = content.split('\n').count();
+    if line >= line_count {
+        return Err(KernError::OutOfRange(format!(
+            "line {line} of a {line_count}-line document"
+        )));
+    }
+    Ok(())
+}
+
+/// Fail with `KernError::OutOfRange` unless `offset` lies within `content`
+fn check_offset(content: &str, offset: usize) -> Result<(), KernError> {
+    let len = content.chars().count();
+    if offset > len {
+        return Err(KernError::OutOfRange(format!(
+            "offset {offset} of {len} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// Decode one or more `EditDelta`s passed in from JS
+fn decode_delta<T: serde::de::DeserializeOwned>(value: JsValue) -> Result<T, KernError> {
+    serde_wasm_bindgen::from_value(value).map_err(|e| KernError::InvalidDelta(e.to_string()))
+}
+
+/// Stable IDs for each line of `text`
+///
+/// A line is identified by the op ID of the newline terminating it, which only
+/// changes when that line is joined with the next. The unterminated last line
+/// is always `"end"`.
+fn line_ids(text: &LoroText, content: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+    let mut pos = 0;
+    for line in content.split('\n') {
+        pos += line.chars().count();
+        ids.push(line_id(text, pos));
+        pos += 1;
+    }
+    ids
+}
+
+/// The ID of the line terminated by the newline at `pos`, `"end"` past the
+/// end of `text`
+fn line_id(text: &LoroText, pos: usize) -> String {
+    text.get_cursor(pos, Side::Middle)
+        .and_then(|cursor| cursor.id)
+        .map_or_else(|| "end".to_string(), |id| id.to_string())
+}
+
+/// Formatting spans of `text`, grouped by line
+fn line_marks(text: &LoroText) -> Vec<Vec<MarkSpan>> {
+    delta_marks(text.to_delta())
+}
+
+/// Formatting spans of a rich-text delta, grouped by line
+fn delta_marks(deltas: Vec<TextDelta>) -> Vec<Vec<MarkSpan>> {
+    let mut lines = vec![Vec::new()];
+    let mut col = 0;
+    for delta in deltas {
+        let TextDelta::Insert { insert, attributes } = delta else {
+            continue;
+        };
+        let attributes = attributes.unwrap_or_default();
+        for (i, segment) in insert.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(Vec::new());
+                col = 0;
+            }
+            let len = segment.chars().count();
+            if len == 0 {
+                continue;
+            }
+            let spans = lines.last_mut().unwrap();
+            for (key, value) in &attributes {
+                spans.push(MarkSpan {
+                    start: col,
+                    end: col + len,
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+            col += len;
+        }
+    }
+    lines
+}
+
+/// Split `content` into paragraphs on blank lines
+///
+/// A line opening a ``` or ~~~ fence starts a block of its own that runs to
+/// the matching closing fence, or the end of the document if there's none.
+fn split_paragraphs(content: &str) -> Vec<Paragraph> {
+    let mut paragraphs = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut start = 0;
+    let mut fence: Option<&str> = None;
+    let mut flush = |current: &mut Vec<&str>, start: usize| {
+        if !current.is_empty() {
+            paragraphs.push(Paragraph {
+                start_line: start,
+                end_line: start + current.len() - 1,
+                text: current.join("\n"),
+            });
+            current.clear();
+        }
+    };
+
+    for (i, line) in content.split('\n').enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            current.push(line);
+            if trimmed.starts_with(marker) {
+                fence = None;
+                flush(&mut current, start);
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            flush(&mut current, start);
+            fence = Some(marker);
+            start = i;
+            current.push(line);
+        } else if line.trim().is_empty() {
+            flush(&mut current, start);
+        } else {
+            if current.is_empty() {
+                start = i;
+            }
+            current.push(line);
+        }
+    }
+    flush(&mut current, start);
+    paragraphs
+}
+
+/// Render `text` and its marks as Markdown
+fn markdown(text: &LoroText) -> String {
+    let mut out = String::new();
+    for delta in text.to_delta() {
+        let TextDelta::Insert { insert, attributes } = delta else {
+            continue;
+        };
+        let attributes = attributes.unwrap_or_default();
+        for (i, segment) in insert.split('\n').enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            // Inline syntax cannot span lines, so each line's slice is wrapped separately
+            if segment.is_empty() {
+                continue;
+            }
+            let mut wrapped = segment.to_string();
+            for (key, open, close) in [
+                ("code", "`", "`"),
+                ("strikethrough", "~~", "~~"),
+                ("italic", "*", "*"),
+                ("bold", "**", "**"),
+            ] {
+                if attributes.get(key).is_some_and(is_set) {
+                    wrapped = format!("{open}{wrapped}{close}");
+                }
+            }
+            if let Some(LoroValue::String(url)) = attributes.get("link") {
+                wrapped = format!("[{wrapped}]({})", url.as_str());
+            }
+            out.push_str(&wrapped);
+        }
+    }
+    out
+}
+
+/// Delete every live key of `map`; `LoroMap::clear` also re-deletes
+/// tombstoned keys, spending ops the limit checks never charged
+fn clear_live(map: &LoroMap) -> Result<(), KernError> {
+    for key in map.keys().collect::<Vec<_>>() {
+        map.delete(&key)?;
+    }
+    Ok(())
+}
+
+/// Whether a mark value turns its style on
+fn is_set(value: &LoroValue) -> bool {
+    !matches!(value, LoroValue::Null | LoroValue::Bool(false))
+}
+
+/// How many ops of the import blob `payload` are missing from `vv`
+fn new_blob_ops(payload: &[u8], vv: &VersionVector) -> Result<usize, KernError> {
+    let meta = LoroDoc::decode_import_blob_meta(payload, false)?;
+    Ok(meta
+        .partial_end_vv
+        .iter()
+        .map(|(peer, &end)| {
+            let start = meta.partial_start_vv.get(peer).copied().unwrap_or(0);
+            let known = vv.get(peer).copied().unwrap_or(0);
+            (end - start.max(known)).max(0) as usize
+        })
+        .sum())
+}
+
+/// The version clock persisted in `doc`, 0 if it has none
+fn stored_version(doc: &LoroDoc) -> u64 {
+    stored_count(doc, VERSION_KEY)
+}
+
+/// The revision count persisted in `doc`, or 0 if it never recorded one
+fn stored_revisions(doc: &LoroDoc) -> u64 {
+    let LoroValue::Map(meta) = doc.get_map(META).get_value() else {
+        return 0;
+    };
+    meta.iter()
+        .filter(|(key, _)| {
+            key.strip_prefix(REVISIONS_KEY)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+        })
+        .filter_map(|(_, count)| match count {
+            LoroValue::I64(count) => Some((*count).max(0) as u64),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// A non-negative integer stored in `META` under `key`, or 0
+fn stored_count(doc: &LoroDoc, key: &str) -> u64 {
+    match doc.get_map(META).get(key) {
+        Some(ValueOrContainer::Value(LoroValue::I64(count))) => count.max(0) as u64,
+        _ => 0,
+    }
+}
+
+/// Current Unix time in seconds
+fn now_secs() -> i64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        (js_sys::Date::now() / 1000.0) as i64
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64)
+    }
+}
+
+/// Milliseconds elapsed since it was started, for `import_timed`
+struct Stopwatch {
+    #[cfg(target_arch = "wasm32")]
+    start: f64,
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    fn start() -> Self {
+        #[cfg(target_arch = "wasm32")]
+        let start = Self::now_ms();
+        #[cfg(not(target_arch = "wasm32"))]
+        let start = std::time::Instant::now();
+        Stopwatch { start }
+    }
+
+    fn elapsed_ms(&self) -> f64 {
+        #[cfg(target_arch = "wasm32")]
+        {
+            Self::now_ms() - self.start
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.start.elapsed().as_secs_f64() * 1000.0
+        }
+    }
+
+    /// `performance.now()` where the global scope has it, in windows and
+    /// workers alike, else the coarser `Date.now()`
+    #[cfg(target_arch = "wasm32")]
+    fn now_ms() -> f64 {
+        let performance = js_sys::Reflect::get(&js_sys::global(), &"performance".into());
+        performance
+            .ok()
+            .and_then(|performance| {
+                let now = js_sys::Reflect::get(&performance, &"now".into()).ok()?;
+                let now: js_sys::Function = now.dyn_into().ok()?;
+                now.call0(&performance).ok()?.as_f64()
+            })
+            .unwrap_or_else(js_sys::Date::now)
+    }
+}
+
+/// Prefix a Loro snapshot with the Kern envelope header
+fn seal_snapshot(loro: Vec<u8>) -> Vec<u8> {
+    seal_with_header(&[], loro)
+}
+
+/// Prefix a Loro snapshot with the envelope and a metadata header
+///
+/// The payload is a little-endian `u32` header length, the header, then
+/// the Loro bytes. An empty header means no metadata.
+fn seal_with_header(header: &[u8], loro: Vec<u8>) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 5 + header.len() + loro.len());
+    blob.extend_from_slice(SNAPSHOT_MAGIC);
+    blob.push(SNAPSHOT_VERSION);
+    blob.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    blob.extend_from_slice(header);
+    blob.extend(loro);
+    blob
+}
+
+/// The Loro payload of an enveloped snapshot or a raw Loro export, migrated
+/// to the current format version
+fn open_blob(data: &[u8]) -> Result<Cow<'_, [u8]>, KernError> {
+    let payload = upgraded_payload(data)?;
+    let (_, skip) = split_header(&payload)?;
+    Ok(match payload {
+        Cow::Borrowed(payload) => Cow::Borrowed(&payload[skip..]),
+        Cow::Owned(mut payload) => {
+            payload.drain(..skip);
+            Cow::Owned(payload)
+        }
+    })
+}
+
+/// The envelope payload of a blob, migrated to the current format version
+fn upgraded_payload(data: &[u8]) -> Result<Cow<'_, [u8]>, KernError> {
+    let (mut version, payload) = split_envelope(data)?;
+    let mut payload = Cow::Borrowed(payload);
+    while version < SNAPSHOT_VERSION {
+        payload = upgrade(version, payload)?;
+        version += 1;
+    }
+    Ok(payload)
+}
+
+/// The metadata header of a current payload and where the Loro bytes start
+fn split_header(payload: &[u8]) -> Result<(&[u8], usize), KernError> {
+    let len = payload
+        .first_chunk::<4>()
+        .map(|len| u32::from_le_bytes(*len) as usize)
+        .ok_or(KernError::NotKernSnapshot)?;
+    let header = payload.get(4..4 + len).ok_or(KernError::NotKernSnapshot)?;
+    Ok((header, 4 + len))
+}
+
+/// Format version and payload of a blob
+fn split_envelope(data: &[u8]) -> Result<(u8, &[u8]), KernError> {
+    if let Some(rest) = data.strip_prefix(SNAPSHOT_MAGIC) {
+        return match rest.split_first() {
+            Some((&version, payload)) if (1..=SNAPSHOT_VERSION).contains(&version) => {
+                Ok((version, payload))
+            }
+            Some((&version, _)) => Err(KernError::UnsupportedVersion(version)),
+            None => Err(KernError::NotKernSnapshot),
+        };
+    }
+    if data.starts_with(LORO_MAGIC) {
+        Ok((0, data))
+    } else {
+        Err(KernError::NotKernSnapshot)
+    }
+}
+
+/// Rewrite a payload of format version `from` into version `from + 1`
+fn upgrade(from: u8, payload: Cow<'_, [u8]>) -> Result<Cow<'_, [u8]>, KernError> {
+    match from {
+        // The envelope was added around an unchanged Loro layout
+        0 => Ok(payload),
+        // Version 2 put a length-prefixed metadata header before the Loro bytes
+        1 => {
+            let mut upgraded = vec![0; 4];
+            upgraded.extend_from_slice(&payload);
+            Ok(Cow::Owned(upgraded))
+        }
+        _ => Err(KernError::UnsupportedVersion(from)),
+    }
+}
+
+/// Convert CRLF and lone CR line endings to LF
+fn normalize_newlines(content: &str) -> Cow<'_, str> {
+    if content.contains('\r') {
+        content.replace("\r\n", "\n").replace('\r', "\n").into()
+    } else {
+        content.into()
+    }
+}
+
+/// Classify the line endings in `content`; text without any counts as LF
+fn line_ending_style(content: &str) -> &'static str {
+    let crlf = content.matches("\r\n").count();
+    let all = content.matches(['\r', '\n']).count();
+    match crlf {
+        0 if !content.contains('\r') => "lf",
+        n if n > 0 && all == 2 * n => "crlf",
+        _ => "mixed",
+    }
+}
+
+/// The splices rewriting every line ending in `content` to CRLF or LF,
+/// back to front so each leaves the positions of the next valid
+fn ending_splices(content: &str, crlf: bool) -> Vec<Splice> {
+    let chars: Vec<char> = content.chars().collect();
+    let splice = |start, delete, insert: &str| Splice {
+        start,
+        delete,
+        insert: Some(insert.to_string()).filter(|s| !s.is_empty()),
+    };
+    let mut splices = Vec::new();
+    for pos in (0..chars.len()).rev() {
+        let after_cr = pos > 0 && chars[pos - 1] == '\r';
+        let before_lf = chars.get(pos + 1) == Some(&'\n');
+        splices.push(match (chars[pos], crlf) {
+            ('\n', true) if !after_cr => splice(pos, 0, "\r"),
+            ('\r', true) if !before_lf => splice(pos + 1, 0, "\n"),
+            ('\r', false) if before_lf => splice(pos, 1, ""),
+            ('\r', false) => splice(pos, 1, "\n"),
+            _ => continue,
+        });
+    }
+    splices
+}
+
+/// Rows `line` fills when word-wrapped at `width` columns, one per char
+///
+/// Whitespace may hang past the edge; words wider than a row are broken.
+fn visual_rows(line: &str, width: usize) -> usize {
+    let width = width.max(1);
+    let mut rows = 1;
+    let mut col = 0;
+    for token in line.split_inclusive(char::is_whitespace) {
+        let word = token.trim_end_matches(char::is_whitespace).chars().count();
+        if col > 0 && col + word > width {
+            rows += 1;
+            col = 0;
+        }
+        col += word;
+        while col > width {
+            rows += 1;
+            col -= width;
+        }
+        col += token.chars().count() - word;
+    }
+    rows
+}
+
+/// Column of the word boundary before or after `col` in `line`, skipping
+/// over a whitespace run next to the caret
+fn word_edge(line: &str, col: usize, forward: bool) -> usize {
+    let segments = word_segments(line);
+
+    if forward {
+        let Some(i) = segments.iter().position(|&(s, e, _)| s <= col && col < e) else {
+            return col;
+        };
+        match segments.get(i + 1) {
+            Some(&(_, next_end, _)) if segments[i].2 => next_end,
+            _ => segments[i].1,
+        }
+    } else {
+        let Some(i) = segments.iter().position(|&(s, e, _)| s < col && col <= e) else {
+            return col;
+        };
+        match i.checked_sub(1) {
+            Some(prev) if segments[i].2 => segments[prev].0,
+            _ => segments[i].0,
+        }
+    }
+}
+
+/// Unicode word segments of `line` as `(start_col, end_col, is_whitespace)`
+fn word_segments(line: &str) -> Vec<(usize, usize, bool)> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for segment in line.split_word_bounds() {
+        let end = start + segment.chars().count();
+        segments.push((start, end, segment.chars().all(char::is_whitespace)));
+        start = end;
+    }
+    segments
+}
+
+/// Convert a line/col pair into a Unicode scalar offset into `content`
+fn char_offset(content: &str, line: usize, col: usize) -> usize {
+    let mut pos = 0;
+    for (i, l) in content.split('\n').enumerate() {
+        let len = l.chars().count();
+        if i == line {
+            pos += col.min(len);
+            break;
+        }
+        pos += len + 1; // +1 for newline
+    }
+    pos
+}
+
+/// Convert a Unicode scalar offset into a line/col position in `content`
+fn line_col(content: &str, offset: usize) -> Position {
+    let mut line = 0;
+    let mut col = 0;
+    for c in content.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            col = 0;
+        } else {
+            col += 1;
+        }
+    }
+    Position { line, col }
+}
+
+impl Default for KernCore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for KernEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A snapshot split for streaming, from `export_snapshot_chunked`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotChunks {
+    /// Combined length of all chunks, for progress reporting
+    pub total_len: usize,
+    pub chunks: Vec<Vec<u8>>,
+}
+
+/// Chunks of an import being streamed in, applied in one go by `finish`
+#[wasm_bindgen]
+pub struct ImportSession {
+    buffer: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl ImportSession {
+    /// Append the next chunk of the blob
+    #[wasm_bindgen]
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Import the assembled blob into `engine`, returning the changed range
+    ///
+    /// Sessions hold no reference to their engine, since JS can't lend one
+    /// across calls, so it is passed back in here.
+    #[wasm_bindgen]
+    pub fn finish(self, engine: &mut KernEngine) -> Result<JsValue, KernError> {
+        let changed = self.finish_into(&mut engine.core)?;
+        Ok(serde_wasm_bindgen::to_value(&changed)?)
+    }
+}
+
+impl ImportSession {
+    /// Import the assembled blob into `engine`
+    pub fn finish_into(self, engine: &mut KernCore) -> Result<LineRange, KernError> {
+        engine.import_bytes(&self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_health_check() {
+        assert_eq!(check_health(), "Kern Engine: Active (Rust/WASM)");
+    }
+
+    #[test]
+    fn test_with_empty_content() {
+        let engine = KernCore::with_content("");
+        assert_eq!(engine.get_text(), "");
+        assert_eq!(engine.view().lines.len(), 1);
+        assert!(!engine.can_undo());
+    }
+
+    #[test]
+    fn test_crlf_normalized_on_ingestion() {
+        let engine = KernCore::with_content("a\r\nb\rc");
+        assert_eq!(engine.get_text(), "a\nb\nc");
+
+        let mut engine = KernCore::new();
+        engine.set_text("one\r\ntwo\r\n").unwrap();
+        assert_eq!(engine.get_text(), "one\ntwo\n");
+        assert_eq!(engine.get_line_count(), 3);
+
+        // Imports apply as sent, without local ops of their own
+        let doc = LoroDoc::new();
+        doc.get_text(DEFAULT_CONTAINER).insert(0, "x\r\ny").unwrap();
+        doc.commit();
+        let mut engine = KernCore::with_content("");
+        engine
+            .import_bytes(&doc.export(ExportMode::Snapshot).unwrap())
+            .unwrap();
+        assert_eq!(engine.get_text(), "x\r\ny");
+        assert_eq!(engine.doc.oplog_vv(), doc.oplog_vv());
+        assert!(!engine.can_undo());
+    }
+
+    #[test]
+    fn test_line_ending_detection_and_conversion() {
+        let mut engine = KernCore::new();
+        engine.set_text("one\ntwo\n").unwrap();
+        assert_eq!(engine.detect_line_ending(), "lf");
+
+        engine.convert_line_endings("crlf").unwrap();
+        assert_eq!(engine.get_text(), "one\r\ntwo\r\n");
+        assert_eq!(engine.detect_line_ending(), "crlf");
+
+        // A raw edit mixes in a bare LF and a lone CR
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 0,
+                insert: Some("zero\nhalf\r".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert_eq!(engine.detect_line_ending(), "mixed");
+
+        engine.convert_line_endings("lf").unwrap();
+        assert_eq!(engine.get_text(), "zero\nhalf\none\ntwo\n");
+        assert_eq!(engine.detect_line_ending(), "lf");
+        assert!(matches!(
+            engine.convert_line_endings("cr"),
+            Err(KernError::InvalidLineEnding(_))
+        ));
+    }
+
+    #[test]
+    fn test_crlf_conversion_survives_import() {
+        let mut a = KernCore::with_content("one\ntwo");
+        let mut b = a.fork();
+        a.convert_line_endings("crlf").unwrap();
+        b.import_bytes(&a.export_updates().unwrap()).unwrap();
+        assert_eq!(b.detect_line_ending(), "crlf");
+
+        b.insert_at(0, "x").unwrap();
+        a.import_bytes(&b.export_updates().unwrap()).unwrap();
+        assert_eq!(a.get_text(), "xone\r\ntwo");
+        assert_eq!(a.detect_line_ending(), "crlf");
+        // The import left A with no ops of its own to broadcast
+        assert_eq!(a.doc.oplog_vv(), b.doc.oplog_vv());
+    }
+
+    #[test]
+    fn test_engine_creation() {
+        let engine = KernCore::new();
+        let content = engine.get_text();
+        assert!(content.contains("Welcome to Kern"));
+    }
+
+    #[test]
+    fn test_edit_after_emoji_line() {
+        let mut engine = KernCore::new();
+        engine.set_text("héllo 😀\nworld").unwrap();
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 1,
+                col: 0,
+                insert: Some("new ".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "héllo 😀\nnew world");
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 7,
+                insert: Some("!".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "héllo 😀!\nnew world");
+    }
+
+    #[test]
+    fn test_export_updates_is_incremental() {
+        let mut engine = KernCore::new();
+        engine
+            .set_text(&"lorem ipsum dolor sit amet\n".repeat(50))
+            .unwrap();
+        engine.export_updates().unwrap();
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 0,
+                insert: Some("a".into()),
+                delete: None,
+            })
+            .unwrap();
+        let first = engine.export_updates().unwrap();
+        engine
+            .apply_delta(&EditDelta {
+                line: 1,
+                col: 0,
+                insert: Some("b".into()),
+                delete: None,
+            })
+            .unwrap();
+        let second = engine.export_updates().unwrap();
+
+        assert!(!first.is_empty());
+        assert!(second.len() < engine.export_snapshot().unwrap().len());
+
+        engine.reset_update_cursor();
+        assert!(engine.export_updates().unwrap().len() > second.len());
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut engine = KernCore::new();
+        let before = engine.get_text();
+        assert!(!engine.can_undo());
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 0,
+                insert: Some("Hello ".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert!(engine.can_undo());
+
+        let version = engine.get_version();
+        assert!(engine.undo().unwrap());
+        assert_eq!(engine.get_text(), before);
+        assert!(engine.get_version() > version);
+
+        assert!(engine.can_redo());
+        assert!(engine.redo().unwrap());
+        assert!(engine.get_text().starts_with("Hello # Welcome"));
+        assert!(!engine.redo().unwrap());
+    }
+
+    #[test]
+    fn test_batch_edits_undo_together() {
+        let mut engine = KernCore::new();
+        engine.set_text("a\nb\nc").unwrap();
+        let insert = |line, col, s: &str| EditDelta {
+            line,
+            col,
+            insert: Some(s.into()),
+            delete: None,
+        };
+
+        let affected = engine
+            .apply_deltas(&[insert(2, 1, "3"), insert(0, 1, "1"), insert(0, 2, "!")])
+            .unwrap();
+        // Everything from the first to the last edited line
+        assert_eq!(affected, LineRange::rewritten(0, 3));
+        assert_eq!(engine.get_text(), "a1!\nb\nc3");
+
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "a\nb\nc");
+    }
+
+    #[test]
+    fn test_failed_batch_applies_nothing() {
+        let mut engine = KernCore::with_content("a\nb\nc");
+        let version = engine.get_version();
+        let insert = |line, s: &str| EditDelta {
+            line,
+            col: 0,
+            insert: Some(s.into()),
+            delete: None,
+        };
+
+        let err = engine
+            .apply_deltas(&[insert(0, "x"), insert(7, "y"), insert(1, "z")])
+            .unwrap_err();
+        assert!(matches!(err, KernError::BatchFailed { index: 1, .. }));
+        assert_eq!(err.code(), "OUT_OF_RANGE");
+        assert_eq!(DocumentError::from(&err).index, Some(1));
+        assert_eq!(engine.get_text(), "a\nb\nc");
+        assert_eq!(engine.get_version(), version);
+        assert!(!engine.has_uncommitted_changes());
+    }
+
+    #[test]
+    fn test_batch_holds_edits_uncommitted() {
+        let mut engine = KernCore::new();
+        engine.set_text("").unwrap();
+        assert!(!engine.has_uncommitted_changes());
+
+        engine.begin_batch();
+        for text in ["a", "b"] {
+            engine
+                .apply_delta(&EditDelta {
+                    line: 0,
+                    col: 1,
+                    insert: Some(text.into()),
+                    delete: None,
+                })
+                .unwrap();
+        }
+        assert!(engine.has_uncommitted_changes());
+
+        engine.commit();
+        assert!(!engine.has_uncommitted_changes());
+        engine.end_batch();
+
+        // Both edits went in as one commit
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "");
+    }
+
+    #[test]
+    fn test_debounced_edits_undo_as_group() {
+        let mut engine = KernCore::with_content("");
+        engine.set_mode(CommitMode::Debounced { ops: 10 });
+        for col in 0..5 {
+            engine
+                .apply_delta(&EditDelta {
+                    line: 0,
+                    col,
+                    insert: Some("x".into()),
+                    delete: None,
+                })
+                .unwrap();
+        }
+        assert!(engine.has_uncommitted_changes());
+
+        engine.flush();
+        assert!(!engine.has_uncommitted_changes());
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "");
+    }
+
+    #[test]
+    fn test_debounced_commits_after_window() {
+        let mut engine = KernCore::with_content("");
+        engine.set_mode(CommitMode::Debounced { ops: 2 });
+        let edit = EditDelta {
+            line: 0,
+            col: 0,
+            insert: Some("x".into()),
+            delete: None,
+        };
+        engine.apply_delta(&edit).unwrap();
+        assert!(engine.has_uncommitted_changes());
+        engine.apply_delta(&edit).unwrap();
+        assert!(!engine.has_uncommitted_changes());
+    }
+
+    #[test]
+    fn test_out_of_range_delete_is_an_error() {
+        let mut engine = KernCore::new();
+        engine.set_text("abc").unwrap();
+
+        let result = engine.apply_delta(&EditDelta {
+            line: 5,
+            col: 0,
+            insert: None,
+            delete: Some(1),
+        });
+        assert!(matches!(result, Err(KernError::OutOfRange(_))));
+        assert_eq!(engine.get_text(), "abc");
+    }
+
+    #[test]
+    fn test_trailing_empty_line_is_addressable() {
+        let mut engine = KernCore::new();
+        engine.set_text("abc\n").unwrap();
+        assert_eq!(engine.get_line_count(), 2);
+        assert_eq!(engine.view().lines.len(), 2);
+        assert_eq!(engine.get_line(1).as_deref(), Some(""));
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 1,
+                col: 0,
+                insert: Some("def".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "abc\ndef");
+        assert_eq!(engine.view().lines[1].content, "def");
+    }
+
+    #[test]
+    fn test_offset_round_trip() {
+        let mut engine = KernCore::new();
+        engine.set_text("héllo 😀\n\nnaïve").unwrap();
+
+        for (line, len) in [(0, 7), (1, 0), (2, 5)] {
+            for col in 0..=len {
+                let offset = engine.offset_of(line, col).unwrap();
+                assert_eq!(engine.position_of(offset).unwrap(), Position { line, col });
+            }
+        }
+        assert_eq!(engine.offset_of(2, 0).unwrap(), 9);
+        assert!(matches!(
+            engine.offset_of(3, 0),
+            Err(KernError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            engine.position_of(15),
+            Err(KernError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_insert_and_delete_at_offset() {
+        let mut engine = KernCore::new();
+        engine.set_text("a😀c").unwrap();
+
+        engine.insert_at(2, "b").unwrap();
+        assert_eq!(engine.get_text(), "a😀bc");
+        engine.delete_at(1, 1).unwrap();
+        assert_eq!(engine.get_text(), "abc");
+        assert!(matches!(
+            engine.delete_at(2, 5),
+            Err(KernError::OutOfRange(_))
+        ));
+        assert_eq!(engine.get_text(), "abc");
+    }
+
+    #[test]
+    fn test_delete_multi_line_selection() {
+        let mut engine = KernCore::new();
+        engine.set_text("first line\nmiddle\nlast line").unwrap();
+
+        // Selection made bottom-up, from "last| line" back to "first| line"
+        let affected = engine
+            .delete_between(Position { line: 2, col: 4 }, Position { line: 0, col: 5 })
+            .unwrap();
+        assert_eq!(
+            affected,
+            LineRange {
+                start: 0,
+                end: 1,
+                inserted: 1,
+                removed: 3,
+            }
+        );
+        assert_eq!(engine.get_text(), "first line");
+
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "first line\nmiddle\nlast line");
+    }
+
+    #[test]
+    fn test_delete_past_start_is_capped() {
+        let mut engine = KernCore::new();
+        engine.set_text("abc\ndef").unwrap();
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 2,
+                insert: None,
+                delete: Some(10),
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "c\ndef");
+    }
+
+    #[test]
+    fn test_insert_past_end() {
+        let mut engine = KernCore::new();
+        engine.set_text("abc\n").unwrap();
+
+        // Columns clamp to the end of the line
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 99,
+                insert: Some("!".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "abc!\n");
+
+        // Lines past the one just after the trailing empty line are rejected
+        let result = engine.apply_delta(&EditDelta {
+            line: 3,
+            col: 0,
+            insert: Some("x".into()),
+            delete: None,
+        });
+        assert!(matches!(result, Err(KernError::OutOfRange(_))));
+    }
+
+    #[test]
+    fn test_attributed_edits_in_history() {
+        let mut engine = KernCore::new();
+        let edit = |text: &str| EditDelta {
+            line: 0,
+            col: 0,
+            insert: Some(text.into()),
+            delete: None,
+        };
+        engine.apply_delta_as(&edit("a"), Some("alice")).unwrap();
+        engine
+            .apply_deltas_as(&[edit("b"), edit("c")], Some("bob"))
+            .unwrap();
+
+        let history = engine.history();
+        let messages: Vec<Option<&str>> = history.iter().map(|c| c.message.as_deref()).collect();
+        assert_eq!(messages[..2], [Some("bob"), Some("alice")]);
+        assert_eq!(history[0].peer, engine.get_peer_id());
+        assert!(history[0].timestamp > 0);
+    }
+
+    #[test]
+    fn test_blame_attributes_lines_to_peers() {
+        let mut a = KernCore::with_content("");
+        a.set_peer_id("1").unwrap();
+        a.set_text("first\nsecond\n\nfourth").unwrap();
+        let mut b = KernCore::new();
+        b.set_peer_id("2").unwrap();
+        b.set_text("").unwrap();
+        b.import_bytes(&a.export_snapshot().unwrap()).unwrap();
+
+        b.apply_delta(&EditDelta {
+            line: 1,
+            col: 6,
+            insert: Some("!".into()),
+            delete: None,
+        })
+        .unwrap();
+        a.import_bytes(&b.export_snapshot().unwrap()).unwrap();
+
+        let peers: Vec<(usize, String)> = a
+            .blame_lines()
+            .into_iter()
+            .map(|blame| (blame.line, blame.peer))
+            .collect();
+        assert_eq!(
+            peers,
+            [
+                (0, "1".to_string()),
+                (1, "2".to_string()),
+                (2, "1".to_string()),
+                (3, "1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_set_text_merges_changes() {
+        let mut a = KernCore::with_content("The cat sat.\nThe end.");
+        a.set_peer_id("1").unwrap();
+        let mut b = a.fork();
+        b.set_peer_id("2").unwrap();
+        a.export_updates().unwrap();
+
+        a.set_text("The dog sat.\nThe end.").unwrap();
+        b.set_text("The cat sat.\nThe very end.").unwrap();
+        a.import_bytes(&b.export_updates().unwrap()).unwrap();
+        b.import_bytes(&a.export_updates().unwrap()).unwrap();
+
+        assert_eq!(a.get_text(), "The dog sat.\nThe very end.");
+        assert_eq!(b.get_text(), a.get_text());
+
+        let frontier = a.current_frontier();
+        a.set_text("later").unwrap();
+        a.checkout(&frontier).unwrap();
+        assert!(matches!(a.set_text("x"), Err(KernError::Detached)));
+    }
+
+    #[test]
+    fn test_paragraphs_split_on_blank_lines() {
+        let engine = KernCore::with_content("# Title\n\nfirst line\nsecond line\n\n\nlast");
+        let paragraphs = engine.paragraphs();
+        let spans: Vec<(usize, usize, &str)> = paragraphs
+            .iter()
+            .map(|p| (p.start_line, p.end_line, p.text.as_str()))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (0, 0, "# Title"),
+                (2, 3, "first line\nsecond line"),
+                (6, 6, "last"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paragraphs_keep_code_fences_whole() {
+        let engine = KernCore::with_content("intro\n```rust\nfn a() {}\n\nfn b() {}\n```\nafter");
+        let paragraphs = engine.paragraphs();
+        assert_eq!(paragraphs.len(), 3);
+        assert_eq!(paragraphs[0].text, "intro");
+        assert_eq!(paragraphs[1].start_line, 1);
+        assert_eq!(paragraphs[1].end_line, 5);
+        assert_eq!(paragraphs[1].text, "```rust\nfn a() {}\n\nfn b() {}\n```");
+        assert_eq!(paragraphs[2].text, "after");
+    }
+
+    #[test]
+    fn test_move_line_to_end() {
+        let mut engine = KernCore::with_content("zero\none\ntwo");
+        engine.mark_range(0, 4, "bold", true).unwrap();
+        engine.set_block_type(0, "heading").unwrap();
+
+        assert_eq!(engine.move_line(0, 2).unwrap(), LineRange::rewritten(0, 3));
+        assert_eq!(engine.get_text(), "one\ntwo\nzero");
+        let view = engine.view();
+        assert_eq!(view.lines[2].block, "heading");
+        assert_eq!(view.lines[1].block, "paragraph");
+        let bold = MarkSpan {
+            start: 0,
+            end: 4,
+            key: "bold".into(),
+            value: true.into(),
+        };
+        assert_eq!(view.lines[2].marks, Some(vec![bold]));
+        assert_eq!(view.lines[1].marks, None);
+
+        engine.move_line(2, 0).unwrap();
+        assert_eq!(engine.get_text(), "zero\none\ntwo");
+        assert!(engine.move_line(0, 3).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_line_moves_converge() {
+        let mut a = KernCore::with_content("a\nb\nc\nd");
+        a.set_peer_id("1").unwrap();
+        let mut b = a.fork();
+        b.set_peer_id("2").unwrap();
+        a.export_updates().unwrap();
+
+        a.move_line(0, 3).unwrap();
+        b.move_line(2, 0).unwrap();
+        a.import_bytes(&b.export_updates().unwrap()).unwrap();
+        b.import_bytes(&a.export_updates().unwrap()).unwrap();
+
+        assert_eq!(a.get_text(), b.get_text());
+        let mut lines: Vec<String> = a.get_text().split('\n').map(String::from).collect();
+        lines.sort();
+        assert_eq!(lines, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_core_relays_between_clients() {
+        // A native server holding a KernCore, with no JS involved
+        let mut server = KernCore::with_content("");
+        let mut alice = KernCore::with_content("");
+        alice.set_text("shared notes").unwrap();
+        server
+            .import_bytes(&alice.export_updates().unwrap())
+            .unwrap();
+
+        let mut bob = KernCore::with_content("");
+        bob.import_bytes(&server.export_snapshot().unwrap())
+            .unwrap();
+        bob.insert_at(0, "# ").unwrap();
+        let changed = server.import_bytes(&bob.export_updates().unwrap()).unwrap();
+        assert_eq!(changed, LineRange::rewritten(0, 1));
+
+        let lines: Vec<LineView> = server.view().lines;
+        assert_eq!(lines[0].content, "# shared notes");
+
+        let engine = KernEngine::from(server);
+        assert_eq!(engine.get_text(), engine.core().get_text());
+        assert_eq!(engine.get_line_count(), 1);
+    }
+
+    #[test]
+    fn test_fork_and_merge_back() {
+        let mut original = KernCore::new();
+        original.set_text("one\ntwo").unwrap();
+        let mut fork = original.fork();
+        assert_ne!(fork.get_peer_id(), original.get_peer_id());
+
+        original
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 3,
+                insert: Some("!".into()),
+                delete: None,
+            })
+            .unwrap();
+        fork.apply_delta(&EditDelta {
+            line: 1,
+            col: 3,
+            insert: Some("?".into()),
+            delete: None,
+        })
+        .unwrap();
+        assert_eq!(fork.get_text(), "one\ntwo?");
+
+        original
+            .import_bytes(&fork.export_updates().unwrap())
+            .unwrap();
+        assert_eq!(original.get_text(), "one!\ntwo?");
+    }
+
+    #[test]
+    fn test_distinct_peers_merge() {
+        let mut a = KernCore::new();
+        let mut b = KernCore::new();
+        a.set_peer_id("1").unwrap();
+        b.set_peer_id("2").unwrap();
+        assert_eq!(a.get_peer_id(), "1");
+        assert_eq!(b.get_peer_id(), "2");
+
+        a.set_text("from a").unwrap();
+        b.set_text("from b").unwrap();
+        a.import_bytes(&b.export_snapshot().unwrap()).unwrap();
+        b.import_bytes(&a.export_snapshot().unwrap()).unwrap();
+
+        assert_eq!(a.get_text(), b.get_text());
+        assert!(a.get_text().contains("from a"));
+        assert!(a.get_text().contains("from b"));
+    }
+
+    #[test]
+    fn test_redacted_snapshot_drops_deleted_text() {
+        let mut engine = KernCore::with_content("");
+        engine.set_text("public\nhunter2 secret\n").unwrap();
+        engine.set_text("public\n").unwrap();
+        engine.mark_range(0, 6, "bold", true).unwrap();
+        engine.set_block_type(0, "heading").unwrap();
+
+        let blob = engine.export_redacted_snapshot().unwrap();
+        assert!(!blob.windows(7).any(|w| w == b"hunter2"));
+
+        let mut copy = KernCore::with_content("");
+        copy.import_bytes(&blob).unwrap();
+        assert_eq!(copy.get_text(), "public\n");
+        assert_eq!(copy.view().lines[0].block, "heading");
+        assert_eq!(copy.history().len(), 1);
+        assert_eq!(copy.view().lines[0].marks, engine.view().lines[0].marks);
+        assert!(copy.history()[0].message.is_none());
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let mut engine = KernCore::with_content("abc");
+        let err = engine.delete_at(2, 5).unwrap_err();
+        assert_eq!(err.code(), "OUT_OF_RANGE");
+        let shape = DocumentError::from(&err);
+        assert_eq!(shape.code, "OUT_OF_RANGE");
+        assert_eq!(shape.message, err.to_string());
+
+        let err = engine.import_bytes(b"KERN\x01garbage").unwrap_err();
+        assert_eq!(err.code(), "IMPORT_FAILED");
+    }
+
+    #[test]
+    fn test_chunked_import_matches_single_shot() {
+        let mut source = KernCore::new();
+        source.set_text("alpha\nbeta\ngamma").unwrap();
+        let snapshot = source.export_snapshot().unwrap();
+
+        let mut whole = KernCore::with_content("");
+        let expected = whole.import_bytes(&snapshot).unwrap();
+
+        let mut chunked = KernCore::with_content("");
+        let mut session = chunked.begin_import();
+        for chunk in snapshot.chunks(7) {
+            session.push(chunk);
+        }
+        assert_eq!(session.finish_into(&mut chunked).unwrap(), expected);
+        assert_eq!(chunked.get_text(), whole.get_text());
+    }
+
+    #[test]
+    fn test_import_reports_changed_lines() {
+        let mut a = KernCore::new();
+        a.set_text("zero\none\ntwo\nthree\nfour").unwrap();
+        let mut b = KernCore::new();
+        b.set_text("").unwrap();
+        b.import_bytes(&a.export_snapshot().unwrap()).unwrap();
+        a.export_updates().unwrap();
+
+        a.apply_delta(&EditDelta {
+            line: 3,
+            col: 5,
+            insert: Some("!".into()),
+            delete: None,
+        })
+        .unwrap();
+        let changed = b.import_bytes(&a.export_updates().unwrap()).unwrap();
+
+        assert_eq!(changed, LineRange::rewritten(3, 1));
+        assert_eq!(b.get_text(), a.get_text());
+    }
+
+    #[test]
+    fn test_rejects_foreign_and_corrupt_blobs() {
+        let mut engine = KernCore::new();
+        let snapshot = engine.export_snapshot().unwrap();
+        assert!(snapshot.starts_with(SNAPSHOT_MAGIC));
+
+        let zip = b"PK\x03\x04not a kern file";
+        assert!(matches!(
+            engine.import_bytes(zip),
+            Err(KernError::NotKernSnapshot)
+        ));
+
+        let mut future = snapshot.clone();
+        future[SNAPSHOT_MAGIC.len()] = 99;
+        assert!(matches!(
+            engine.import_bytes(&future),
+            Err(KernError::UnsupportedVersion(99))
+        ));
+
+        let truncated = &snapshot[..snapshot.len() / 2];
+        assert!(matches!(
+            engine.import_bytes(truncated),
+            Err(KernError::Loro(_))
+        ));
+        assert_eq!(engine.get_text(), "# Welcome to Kern\n\nStart typing...");
+    }
+
+    #[test]
+    fn test_delete_word_backward_mid_line() {
+        let mut engine = KernCore::with_content("first line\nsay hello world");
+        engine.delete_word(1, 15, false).unwrap();
+        assert_eq!(engine.get_text(), "first line\nsay hello ");
+        engine.delete_word(1, 10, false).unwrap();
+        assert_eq!(engine.get_text(), "first line\nsay ");
+
+        // Mid-word, only the part before the caret goes
+        engine.delete_word(1, 2, false).unwrap();
+        assert_eq!(engine.get_text(), "first line\ny ");
+
+        // At line start the lines join, as one undo step
+        engine.delete_word(1, 0, false).unwrap();
+        assert_eq!(engine.get_text(), "first liney ");
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "first line\ny ");
+    }
+
+    #[test]
+    fn test_delete_word_forward_skips_whitespace() {
+        let mut engine = KernCore::with_content("hello   wide world\nnext");
+        engine.delete_word(0, 5, true).unwrap();
+        assert_eq!(engine.get_text(), "hello world\nnext");
+        engine.delete_word(0, 0, true).unwrap();
+        assert_eq!(engine.get_text(), " world\nnext");
+
+        // At line end the following line is joined on
+        engine.delete_word(0, 6, true).unwrap();
+        assert_eq!(engine.get_text(), " worldnext");
+        assert_eq!(
+            engine.delete_word(0, 10, true).unwrap(),
+            LineRange::default()
+        );
+    }
+
+    #[test]
+    fn test_version_resumes_after_reload() {
+        let mut source = KernCore::with_content("draft");
+        for _ in 0..3 {
+            source.insert_at(0, "x").unwrap();
+        }
+        source.undo().unwrap();
+        let saved = source.get_version();
+        assert_eq!(saved, 4);
+
+        let mut engine = KernCore::new();
+        engine
+            .import_bytes(&source.export_snapshot().unwrap())
+            .unwrap();
+        assert_eq!(engine.get_version(), saved);
+        engine.insert_at(0, "y").unwrap();
+        assert_eq!(engine.get_version(), saved + 1);
+
+        // Later edits elsewhere carry the clock forward on import
+        source.insert_at(0, "z").unwrap();
+        source.insert_at(0, "z").unwrap();
+        engine
+            .import_bytes(&source.export_snapshot().unwrap())
+            .unwrap();
+        assert_eq!(engine.get_version(), saved + 2);
+        assert_eq!(KernCore::new().get_version(), 0);
+    }
+
+    #[test]
+    fn test_replace_next_replaces_one_match() {
+        let mut engine = KernCore::with_content("cat one\nCat two cat three");
+        let from = Position { line: 0, col: 1 };
+        let caret = engine
+            .replace_next("cat", "tiger", from, false, false)
+            .unwrap();
+        assert_eq!(engine.get_text(), "cat one\ntiger two cat three");
+        assert_eq!(caret, Some(Position { line: 1, col: 5 }));
+
+        // Past the last match, only a wrapping search finds the first one
+        let end = Position { line: 1, col: 20 };
+        assert_eq!(
+            engine.replace_next("cat", "dog", end, true, false).unwrap(),
+            None
+        );
+        let caret = engine.replace_next("cat", "dog", end, true, true).unwrap();
+        assert_eq!(engine.get_text(), "dog one\ntiger two cat three");
+        assert_eq!(caret, Some(Position { line: 0, col: 3 }));
+
+        // The caret follows the replacement as normalized, not as given
+        let mut engine = KernCore::with_content("foo bar");
+        let from = Position { line: 0, col: 0 };
+        let caret = engine
+            .replace_next("foo", "x\r\ny", from, true, false)
+            .unwrap();
+        assert_eq!(engine.get_text(), "x\ny bar");
+        assert_eq!(caret, Some(Position { line: 1, col: 1 }));
+    }
+
+    #[test]
+    fn test_ops_between_lists_edits() {
+        let mut engine = KernCore::with_content("ab");
+        let old = engine.current_frontier();
+        engine.insert_at(1, "X").unwrap();
+        engine.insert_at(3, "YZ").unwrap();
+        let current = engine.current_frontier();
+
+        let inserts: Vec<(Option<usize>, Option<String>)> = engine
+            .ops_between(&old, &current)
+            .unwrap()
+            .into_iter()
+            .filter(|op| op.kind == "insert")
+            .map(|op| (op.pos, op.text))
+            .collect();
+        assert_eq!(
+            inserts,
+            vec![(Some(1), Some("X".into())), (Some(3), Some("YZ".into()))]
+        );
+        assert!(engine.ops_between(&current, &current).unwrap().is_empty());
+        assert!(matches!(
+            engine.ops_between(&current, &old),
+            Err(KernError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_view_counts_wrapped_rows() {
+        let engine =
+            KernCore::with_content("the quick brown fox jumps\n\nabcdefghijklmnopqrstuvwxy");
+        let rows = |view: DocumentView| -> Vec<Option<usize>> {
+            view.lines.iter().map(|line| line.visual_rows).collect()
+        };
+        assert_eq!(rows(engine.view().wrapped(None)), vec![None; 3]);
+        // "the quick" / "brown fox" / "jumps", and a word broken twice
+        assert_eq!(
+            rows(engine.view().wrapped(Some(10))),
+            vec![Some(3), Some(1), Some(3)]
+        );
+        assert_eq!(
+            rows(engine.view_range(0, 1).wrapped(Some(80))),
+            vec![Some(1)]
+        );
+    }
+
+    #[test]
+    fn test_paste_splits_line() {
+        let mut engine = KernCore::with_content("title\nbefore after");
+        let pasted = engine.paste(1, 7, "one\r\ntwo ").unwrap();
+        assert_eq!(engine.get_text(), "title\nbefore one\ntwo after");
+        assert_eq!(
+            pasted,
+            PasteResult {
+                caret: Position { line: 2, col: 4 },
+                start_line: 1,
+                end_line: 2,
+            }
+        );
+
+        // One undo step takes the whole paste back
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "title\nbefore after");
+    }
+
+    #[test]
+    fn test_snapshot_to_view_truncates() {
+        let mut source = KernCore::with_content("# Notes\none\ntwo\nthree");
+        source.set_block_type(0, "heading").unwrap();
+        let snapshot = source.export_snapshot().unwrap();
+
+        let view = snapshot_view(&snapshot, 2).unwrap();
+        let lines: Vec<&str> = view.lines.iter().map(|l| l.content.as_str()).collect();
+        assert_eq!(lines, vec!["# Notes", "one"]);
+        assert_eq!(view.lines[0].block, "heading");
+        assert_eq!(view.total_lines, 4);
+        assert_eq!(snapshot_view(&snapshot, 10).unwrap().lines.len(), 4);
+    }
+
+    #[test]
+    fn test_undo_scope_skips_or_includes_remote_edits() {
+        let mut a = KernCore::with_content("base");
+        let mut b = a.fork();
+        a.insert_at(0, "A1 ").unwrap();
+        b.insert_at(4, " R").unwrap();
+        a.import_bytes(&b.export_snapshot().unwrap()).unwrap();
+        let merged = a.get_text();
+        a.insert_at(merged.chars().count(), " A2").unwrap();
+
+        // Local-only scope reverts our edits around the remote one
+        assert!(a.undo().unwrap());
+        assert_eq!(a.get_text(), merged);
+        assert!(a.undo().unwrap());
+        assert_eq!(a.get_text(), "base R");
+
+        // Undoing everything walks back through the remote edit too
+        let mut c = KernCore::with_content("base");
+        let mut d = c.fork();
+        d.insert_at(0, "R ").unwrap();
+        c.import_bytes(&d.export_snapshot().unwrap()).unwrap();
+        c.insert_at(6, " C").unwrap();
+        c.set_undo_scope(false);
+        assert!(c.undo().unwrap());
+        assert_eq!(c.get_text(), "R base");
+        assert!(c.undo().unwrap());
+        assert_eq!(c.get_text(), "base");
+        assert!(c.redo().unwrap());
+        assert_eq!(c.get_text(), "R base");
+        assert!(c.can_redo());
+        c.insert_at(0, "new ").unwrap();
+        assert!(!c.can_redo());
+    }
+
+    #[test]
+    fn test_global_undo_keeps_checkpoints_and_comments() {
+        let mut a = KernCore::with_content("hello");
+        let mut b = a.fork();
+        let mut c = a.fork();
+        b.insert_at(5, " world").unwrap();
+        c.create_checkpoint("v1").unwrap();
+        let anchor = c.cursor_from_pos(0, 0).unwrap();
+        let head = c.cursor_from_pos(0, 5).unwrap();
+        c.add_comment(&anchor, &head, "note").unwrap();
+        a.import_bytes(&b.export_snapshot().unwrap()).unwrap();
+        a.import_bytes(&c.export_snapshot().unwrap()).unwrap();
+        let created = a.created_at();
+        assert!(created.is_some());
+
+        a.set_undo_scope(false);
+        assert!(a.undo().unwrap());
+        assert_eq!(a.get_text(), "hello");
+        assert_eq!(a.list_checkpoints(), ["v1"]);
+        assert_eq!(a.comments().len(), 1);
+        assert_eq!(a.created_at(), created);
+        assert!(a.redo().unwrap());
+        assert_eq!(a.get_text(), "hello world");
+        assert_eq!(a.list_checkpoints(), ["v1"]);
+    }
+
+    #[test]
+    fn test_multi_line_edit_reports_range() {
+        let mut engine = KernCore::with_content("one\ntwo\nthree");
+        let insert = |line, col, s: &str| EditDelta {
+            line,
+            col,
+            insert: Some(s.into()),
+            delete: None,
+        };
+        // "two" becomes "t" + "X" / "Y" + "wo"
+        let range = engine.apply_delta(&insert(1, 1, "X\nY")).unwrap();
+        assert_eq!(engine.get_text(), "one\ntX\nYwo\nthree");
+        assert_eq!(
+            range,
+            LineRange {
+                start: 1,
+                end: 3,
+                inserted: 2,
+                removed: 1,
+            }
+        );
+
+        // Backspacing across two newlines merges three lines into one
+        let range = engine
+            .apply_delta(&EditDelta {
+                line: 2,
+                col: 1,
+                insert: None,
+                delete: Some(6),
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "onwo\nthree");
+        assert_eq!(
+            range,
+            LineRange {
+                start: 0,
+                end: 1,
+                inserted: 1,
+                removed: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_replace_delta_inserts_where_text_was_deleted() {
+        let mut engine = KernCore::with_content("say abc now\nxyz");
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 7,
+                insert: Some("!".into()),
+                delete: Some(3),
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "say ! now\nxyz");
+
+        // At the end of the document, where the old caret no longer exists
+        engine
+            .apply_delta(&EditDelta {
+                line: 1,
+                col: 3,
+                insert: Some("Q".into()),
+                delete: Some(2),
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "say ! now\nxQ");
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        let source = KernCore::with_content("line one\nline two");
+        let encoded = source.export_snapshot_base64().unwrap();
+        let mut engine = KernCore::with_content("");
+        engine.import_base64(&encoded).unwrap();
+        assert_eq!(engine.get_text(), "line one\nline two");
+    }
+
+    #[test]
+    fn test_malformed_base64_is_an_error() {
+        let mut engine = KernCore::with_content("kept");
+        for bad in ["not base64!", "S0VSTg=", "@@@@"] {
+            let err = engine.import_base64(bad).unwrap_err();
+            assert_eq!(err.code(), "INVALID_BASE64");
+        }
+        // Valid base64 that isn't a snapshot fails at import instead
+        assert!(matches!(
+            engine.import_base64("aGVsbG8="),
+            Err(KernError::NotKernSnapshot)
+        ));
+        assert_eq!(engine.get_text(), "kept");
+    }
+
+    #[test]
+    fn test_stats_per_container() {
+        let mut engine = KernCore::with_content("The body runs\nacross two lines");
+        engine
+            .apply_delta_to(
+                "title",
+                &EditDelta {
+                    line: 0,
+                    col: 0,
+                    insert: Some("Short title".into()),
+                    delete: None,
+                },
+            )
+            .unwrap();
+
+        let title = engine.stats_of("title").unwrap();
+        let body = engine.stats_of(DEFAULT_CONTAINER).unwrap();
+        assert_eq!((title.lines, title.words, title.chars), (1, 2, 11));
+        assert_eq!((body.lines, body.words, body.chars), (2, 6, 30));
+        assert_eq!(body, engine.stats());
+        assert!(matches!(
+            engine.stats_of("missing"),
+            Err(KernError::UnknownContainer(_))
+        ));
+    }
+
+    #[test]
+    fn test_comment_follows_its_text() {
+        let mut engine = KernCore::with_content("one\nfix this bit\nthree");
+        let anchor = engine.cursor_from_pos(1, 4).unwrap();
+        let head = engine.cursor_from_pos(1, 8).unwrap();
+        let id = engine.add_comment(&anchor, &head, "reword").unwrap();
+
+        let mut other = engine.fork();
+        other.insert_at(0, "zero\n").unwrap();
+        engine
+            .import_bytes(&other.export_updates().unwrap())
+            .unwrap();
+        engine.insert_at(9, "please ").unwrap();
+
+        let comments = engine.comments();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].id, id);
+        assert_eq!(comments[0].text, "reword");
+        assert_eq!(comments[0].anchor_pos, Position { line: 2, col: 11 });
+        assert_eq!(comments[0].head_pos, Position { line: 2, col: 15 });
+
+        engine.resolve_comment(&id).unwrap();
+        assert!(engine.comments()[0].resolved);
+        assert!(matches!(
+            engine.resolve_comment("0@1"),
+            Err(KernError::UnknownComment(_))
+        ));
+    }
+
+    #[test]
+    fn test_batched_subscription_coalesces_changes() {
+        let mut engine = KernCore::with_content("a\nb\nc\nd");
+        let calls = Arc::new(Mutex::new(Vec::<Vec<usize>>::new()));
+        let tasks = Arc::new(Mutex::new(Vec::<FlushTask>::new()));
+        let (seen, queue) = (calls.clone(), tasks.clone());
+        engine.subscribe_lines_batched(
+            4,
+            move |lines| seen.lock().unwrap().push(lines.to_vec()),
+            move |task| queue.lock().unwrap().push(task),
+        );
+        let run_tasks = || {
+            let pending: Vec<FlushTask> = tasks.lock().unwrap().drain(..).collect();
+            pending.into_iter().for_each(|task| task());
+        };
+
+        // Three one-char edits stay under the lag, until the microtask runs
+        engine.insert_at(0, "1").unwrap();
+        engine.insert_at(3, "2").unwrap();
+        engine.insert_at(9, "3").unwrap();
+        assert!(calls.lock().unwrap().is_empty());
+        run_tasks();
+        assert_eq!(*calls.lock().unwrap(), vec![vec![0, 1, 2, 3]]);
+
+        // A big edit delivers at once; its scheduled flush finds nothing new
+        engine.insert_at(0, "hello").unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 2);
+        run_tasks();
+        assert_eq!(calls.lock().unwrap().len(), 2);
+
+        engine.insert_at(0, "x").unwrap();
+        engine.unsubscribe();
+        run_tasks();
+        assert_eq!(calls.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_snapshot_meta_reads_without_import() {
+        let mut source = KernCore::with_content("## Field notes\n\nday one");
+        source.insert_at(23, ", sunny").unwrap();
+        let blob = source.export_snapshot_with_meta().unwrap();
+
+        let meta = snapshot_meta(&blob).unwrap().unwrap();
+        assert_eq!(meta.title, "Field notes");
+        assert_eq!(
+            meta.char_count,
+            "## Field notes\n\nday one, sunny".chars().count()
+        );
+        assert!(meta.updated_at > 0);
+        assert_eq!(meta.peers, vec![source.doc.peer_id().to_string()]);
+
+        let mut engine = KernCore::with_content("");
+        engine.import_bytes(&blob).unwrap();
+        assert_eq!(engine.get_text(), "## Field notes\n\nday one, sunny");
+
+        // Blobs saved without a header have no metadata
+        assert_eq!(
+            snapshot_meta(&source.export_snapshot().unwrap()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_loads_and_migrates_older_formats() {
+        let source = KernCore::with_content("saved long ago");
+        let loro = source.doc.export(ExportMode::Snapshot).unwrap();
+        let mut v1 = b"KERN\x01".to_vec();
+        v1.extend_from_slice(&loro);
+
+        for blob in [&loro, &v1] {
+            let mut engine = KernCore::with_content("");
+            engine.import_bytes(blob).unwrap();
+            assert_eq!(engine.get_text(), "saved long ago");
+
+            let migrated = migrate(blob).unwrap();
+            assert_eq!(migrated[SNAPSHOT_MAGIC.len()], SNAPSHOT_VERSION);
+            // An empty metadata header, then the untouched Loro bytes
+            assert_eq!(&migrated[SNAPSHOT_MAGIC.len() + 1..][..4], &[0; 4]);
+            assert_eq!(&migrated[SNAPSHOT_MAGIC.len() + 5..], &loro[..]);
+        }
+
+        let mut bogus = v1.clone();
+        bogus[SNAPSHOT_MAGIC.len()] = 0;
+        assert!(matches!(
+            migrate(&bogus),
+            Err(KernError::UnsupportedVersion(0))
+        ));
+    }
+
+    #[test]
+    fn test_canonical_export_matches_across_import_orders() {
+        let mut a = KernCore::with_content("");
+        a.set_peer_id("1").unwrap();
+        let mut b = a.fork();
+        b.set_peer_id("2").unwrap();
+        let insert = |engine: &mut KernCore, text: &str| {
+            engine
+                .apply_delta(&EditDelta {
+                    line: 0,
+                    col: 0,
+                    insert: Some(text.into()),
+                    delete: None,
+                })
+                .unwrap();
+        };
+        insert(&mut a, "alpha ");
+        let a1 = a.export_updates().unwrap();
+        insert(&mut b, "beta ");
+        let b1 = b.export_updates().unwrap();
+        insert(&mut a, "gamma ");
+        let a2 = a.export_updates().unwrap();
+
+        let mut x = KernCore::with_content("");
+        let mut y = KernCore::with_content("");
+        for update in [&a1, &a2, &b1] {
+            x.import_bytes(update).unwrap();
+        }
+        for update in [&b1, &a1, &a2] {
+            y.import_bytes(update).unwrap();
+        }
+        a.import_bytes(&b1).unwrap();
+        assert_eq!(x.get_text(), y.get_text());
+        assert_eq!(x.get_text(), a.get_text());
+
+        let canonical = a.export_canonical().unwrap();
+        assert_eq!(x.export_canonical().unwrap(), canonical);
+        assert_eq!(y.export_canonical().unwrap(), canonical);
+
+        let mut loaded = KernCore::with_content("");
+        loaded.import_bytes(&canonical).unwrap();
+        assert_eq!(loaded.get_text(), a.get_text());
+    }
+
+    #[test]
+    fn test_compressed_snapshot_round_trip() {
+        let mut engine = KernCore::new();
+        engine
+            .set_text(&"the same line again\n".repeat(200))
+            .unwrap();
+
+        let compressed = engine.export_snapshot_compressed().unwrap();
+        let raw = engine.export_snapshot().unwrap();
+        assert!(compressed.len() < raw.len());
+
+        let mut restored = KernCore::new();
+        restored.set_text("").unwrap();
+        restored.import_compressed(&compressed).unwrap();
+        assert_eq!(restored.get_text(), engine.get_text());
+
+        // Uncompressed saves still load through the same entry point
+        let mut legacy = KernCore::new();
+        legacy.set_text("").unwrap();
+        legacy.import_compressed(&raw).unwrap();
+        assert_eq!(legacy.get_text(), engine.get_text());
+    }
+
+    #[test]
+    fn test_shallow_snapshot_trims_history() {
+        let mut engine = KernCore::new();
+        engine.set_text("").unwrap();
+        for i in 0..200 {
+            let edit = EditDelta {
+                line: 0,
+                col: i,
+                insert: Some("x".into()),
+                delete: None,
+            };
+            engine.apply_delta(&edit).unwrap();
+            engine
+                .apply_delta(&EditDelta {
+                    delete: Some(1),
+                    ..edit
+                })
+                .unwrap();
+            engine
+                .apply_delta(&EditDelta {
+                    line: 0,
+                    col: i,
+                    insert: Some(char::from(b'a' + (i % 26) as u8).to_string()),
+                    delete: None,
+                })
+                .unwrap();
+        }
+
+        let shallow = engine.export_shallow_snapshot(10).unwrap();
+        assert!(shallow.len() < engine.export_snapshot().unwrap().len());
+
+        let restored = LoroDoc::new();
+        restored.import(&open_blob(&shallow).unwrap()).unwrap();
+        assert!(restored.is_shallow());
+        assert_eq!(
+            restored.get_text(DEFAULT_CONTAINER).to_string(),
+            engine.get_text()
+        );
+    }
+
+    #[test]
+    fn test_on_sync_fires_once_per_import() {
+        let mut a = KernCore::new();
+        a.set_text("one\ntwo\nthree").unwrap();
+        let mut b = KernCore::new();
+
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let sink = calls.clone();
+        b.on_sync_with(move |version, lines| sink.borrow_mut().push((version, lines.len())));
+
+        b.import_bytes(&a.export_snapshot().unwrap()).unwrap();
+        assert_eq!(calls.borrow().len(), 1);
+        a.set_text("changed").unwrap();
+        b.import_bytes(&a.export_updates().unwrap()).unwrap();
+
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].0, b.get_version());
+    }
+
+    #[test]
+    fn test_preview_import_leaves_engine_untouched() {
+        let mut a = KernCore::new();
+        a.set_text("one\ntwo").unwrap();
+        let mut b = a.fork();
+        b.apply_delta(&EditDelta {
+            line: 1,
+            col: 3,
+            insert: Some("!".into()),
+            delete: None,
+        })
+        .unwrap();
+
+        let version = a.get_version();
+        let preview = a.preview(&b.export_updates().unwrap()).unwrap();
+        assert_eq!(preview.text, "one\ntwo!");
+        assert_eq!(preview.changed_lines, vec![1]);
+        assert_eq!(a.get_text(), "one\ntwo");
+        assert_eq!(a.get_version(), version);
+    }
+
+    #[test]
+    fn test_out_of_order_import_reports_pending() {
+        let mut a = KernCore::with_content("");
+        let mut b = a.fork();
+        let insert = |engine: &mut KernCore, text: &str| {
+            engine
+                .apply_delta(&EditDelta {
+                    line: 0,
+                    col: 0,
+                    insert: Some(text.into()),
+                    delete: None,
+                })
+                .unwrap();
+        };
+        insert(&mut a, "first");
+        let first = a.export_updates().unwrap();
+        insert(&mut a, "second ");
+        let second = a.export_updates().unwrap();
+
+        let report = b.import_report(&second).unwrap();
+        assert_eq!(report.applied_count, 0);
+        // Seven characters, the version clock and the revision count
+        assert_eq!(report.pending_count, 9);
+        assert!(!report.success);
+        assert_eq!(b.get_text(), "");
+
+        // Both commits, and the creation time recorded by the first
+        let report = b.import_report(&first).unwrap();
+        assert_eq!(report.applied_count, 17);
+        assert_eq!(report.pending_count, 0);
+        assert!(report.success);
+        assert_eq!(b.get_text(), "second first");
+    }
+
+    #[test]
+    fn test_version_vector_sync() {
+        let mut a = KernCore::new();
+        let mut b = KernCore::new();
+        b.set_text(&"shared line\n".repeat(20)).unwrap();
+        a.set_text("").unwrap();
+        a.import_bytes(&b.export_snapshot().unwrap()).unwrap();
+
+        b.apply_delta(&EditDelta {
+            line: 0,
+            col: 0,
+            insert: Some("only on b ".into()),
+            delete: None,
+        })
+        .unwrap();
+
+        let missing = b
+            .export_from_version_vector(&a.get_version_vector())
+            .unwrap();
+        assert!(missing.len() < b.export_snapshot().unwrap().len());
+
+        a.import_bytes(&missing).unwrap();
+        assert_eq!(a.get_text(), b.get_text());
+        assert!(a.doc.oplog_vv().includes_vv(&b.doc.oplog_vv()));
+    }
+
+    #[test]
+    fn test_compare_frontiers() {
+        let mut a = KernCore::new();
+        let edit = EditDelta {
+            line: 0,
+            col: 0,
+            insert: Some("x".into()),
+            delete: None,
+        };
+        let before = a.frontier_ids();
+        a.apply_delta(&edit).unwrap();
+        let after = a.frontier_ids();
+        assert_eq!(after[0].peer, a.get_peer_id());
+        assert_eq!(a.compare(&before, &after).unwrap(), Some(Ordering::Less));
+        assert_eq!(a.compare(&after, &before).unwrap(), Some(Ordering::Greater));
+        assert_eq!(a.compare(&after, &after).unwrap(), Some(Ordering::Equal));
+
+        // Two forks editing independently are concurrent once merged
+        let mut b = a.fork();
+        a.apply_delta(&edit).unwrap();
+        b.apply_delta(&edit).unwrap();
+        let (head_a, head_b) = (a.frontier_ids(), b.frontier_ids());
+        assert!(matches!(
+            a.compare(&head_a, &head_b),
+            Err(KernError::UnknownFrontier)
+        ));
+        a.import_bytes(&b.export_snapshot().unwrap()).unwrap();
+        assert_eq!(a.compare(&head_a, &head_b).unwrap(), None);
+    }
+
+    #[test]
+    fn test_checkout_past_version() {
+        let mut engine = KernCore::new();
+        engine.set_text("first").unwrap();
+        let frontier = engine.current_frontier();
+        engine.set_text("second").unwrap();
+
+        engine.checkout(&frontier).unwrap();
+        assert_eq!(engine.get_text(), "first");
+        let edit = EditDelta {
+            line: 0,
+            col: 0,
+            insert: Some("x".into()),
+            delete: None,
+        };
+        assert!(matches!(
+            engine.apply_delta(&edit),
+            Err(KernError::Detached)
+        ));
+
+        engine.checkout_to_latest();
+        assert_eq!(engine.get_text(), "second");
+        engine.apply_delta(&edit).unwrap();
+    }
+
+    #[test]
+    fn test_char_limit_rejects_growth() {
+        let mut engine = KernCore::with_content("12345");
+        engine.set_limits(Limits {
+            max_chars: Some(8),
+            max_ops: None,
+        });
+        let insert = |s: &str| EditDelta {
+            line: 0,
+            col: 5,
+            insert: Some(s.into()),
+            delete: None,
+        };
+
+        engine.apply_delta(&insert("678")).unwrap();
+        let err = engine.apply_delta(&insert("9")).unwrap_err();
+        assert_eq!(err.code(), "LIMIT_EXCEEDED");
+        assert!(engine.apply_deltas(&[insert("9")]).is_err());
+        assert!(engine.set_text("123456789").is_err());
+        assert!(engine.replace_all("1", "11", true).is_err());
+        assert!(engine.paste(0, 8, "9").is_err());
+        let json = DocumentJson {
+            lines: vec![JsonLine {
+                content: "123456789".into(),
+                marks: Vec::new(),
+                block: "paragraph".into(),
+            }],
+            version: 0,
+        };
+        assert!(engine.from_json(&json).is_err());
+        assert_eq!(engine.get_text(), "12345678");
+
+        let mut small = KernCore::with_content("aaaa");
+        small.set_limits(Limits {
+            max_chars: Some(5),
+            max_ops: None,
+        });
+        assert!(matches!(
+            small.replace_all("a", "bbbbbb", true),
+            Err(KernError::LimitExceeded(_))
+        ));
+        assert_eq!(small.get_text(), "aaaa");
+        assert_eq!(small.replace_all("aa", "b", true).unwrap(), 2);
+
+        let mut big = KernCore::with_content("");
+        big.set_text("way too long for the limit").unwrap();
+        let snapshot = big.export_snapshot().unwrap();
+        let mut target = KernCore::with_content("");
+        target.set_limits(engine.limits);
+        assert!(matches!(
+            target.import_bytes(&snapshot),
+            Err(KernError::LimitExceeded(_))
+        ));
+        assert_eq!(target.get_text(), "");
+        assert_eq!(target.doc.len_ops(), 0);
+    }
+
+    #[test]
+    fn test_op_limit_stops_long_histories() {
+        let mut engine = KernCore::with_content("");
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(33),
+        });
+        let mut accepted = 0;
+        // Two characters, the version clock and the revision count each, plus
+        // the creation time with the first
+        while engine.insert_at(0, "ab").is_ok() {
+            accepted += 1;
+        }
+        assert_eq!(accepted, 8);
+        assert!(matches!(
+            engine.insert_at(0, "x"),
+            Err(KernError::LimitExceeded(_))
+        ));
+        // Deleting still adds ops, so it's refused too
+        assert!(engine.delete_at(0, 1).is_err());
+        assert_eq!(engine.get_text().len(), 16);
+    }
+
+    #[test]
+    fn test_op_limit_charges_set_text_for_its_diff() {
+        let text = "x".repeat(1000);
+        let mut engine = KernCore::with_content(&text);
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(engine.doc.len_ops() + 100),
+        });
+
+        engine.set_text(&format!("{text}z")).unwrap();
+        engine.apply_external_text(&format!("y{text}z")).unwrap();
+        assert!(matches!(
+            engine.set_text(&"w".repeat(1000)),
+            Err(KernError::LimitExceeded(_))
+        ));
+        assert_eq!(engine.get_text(), format!("y{text}z"));
+    }
+
+    #[test]
+    fn test_op_limit_covers_every_mutator() {
+        let mut engine = KernCore::with_content("one\ntwo\nthree");
+        engine.set_block_type(0, "heading").unwrap();
+        let anchor = engine.cursor_from_pos(1, 0).unwrap();
+        let head = engine.cursor_from_pos(1, 3).unwrap();
+        let comment = engine.add_comment(&anchor, &head, "note").unwrap();
+        // Room for the bookkeeping written with a commit, but no edit
+        let ops = engine.doc.len_ops();
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops + 2),
+        });
+
+        let refused =
+            |result: Result<(), KernError>| matches!(result, Err(KernError::LimitExceeded(_)));
+        assert!(refused(engine.replace_all("o", "0", true).map(drop)));
+        assert!(refused(engine.move_line(0, 2).map(drop)));
+        assert!(refused(engine.mark_range(0, 3, "bold", true)));
+        assert!(refused(engine.unmark(0, 3, "bold")));
+        assert!(refused(engine.set_block_type(1, "code")));
+        assert!(refused(engine.convert_line_endings("crlf")));
+        assert!(refused(
+            engine
+                .delete_between(Position { line: 0, col: 0 }, Position { line: 0, col: 2 },)
+                .map(drop)
+        ));
+        assert!(refused(engine.clear_container(DEFAULT_CONTAINER)));
+        assert!(refused(
+            engine.add_comment(&anchor, &head, "more").map(drop)
+        ));
+        assert!(refused(engine.resolve_comment(&comment)));
+        let json = engine.to_json();
+        assert!(refused(engine.from_json(&json)));
+        assert_eq!(engine.doc.len_ops(), ops);
+        assert_eq!(engine.get_text(), "one\ntwo\nthree");
+        assert_eq!(engine.view().lines[0].block, "heading");
+        assert!(engine.list_checkpoints().is_empty());
+        assert!(!engine.comments()[0].resolved);
+
+        // Each is charged what it adds, not more
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops + 2 + MARK_OPS),
+        });
+        engine.mark_range(0, 3, "bold", true).unwrap();
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(engine.doc.len_ops() + 2 + 16),
+        });
+        engine.move_line(0, 2).unwrap();
+        assert_eq!(engine.get_text(), "two\nthree\none");
+
+        // Deleting 13 characters and the old heading, then a marked heading
+        let json = DocumentJson {
+            lines: vec![JsonLine {
+                content: "x".into(),
+                marks: vec![MarkSpan {
+                    start: 0,
+                    end: 1,
+                    key: "bold".into(),
+                    value: true.into(),
+                }],
+                block: "heading".into(),
+            }],
+            version: 0,
+        };
+        let cost = 13 + 1 + 1 + MARK_OPS + 1;
+        let ops = engine.doc.len_ops() + 2;
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops + cost - 1),
+        });
+        assert!(refused(engine.from_json(&json)));
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops + cost),
+        });
+        engine.from_json(&json).unwrap();
+        assert_eq!(engine.doc.len_ops(), ops + cost);
+
+        // Checkpoints and imports write no version clock or revision count
+        let ops = engine.doc.len_ops();
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops),
+        });
+        assert!(refused(engine.create_checkpoint("v1")));
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops + 1),
+        });
+        engine.create_checkpoint("v1").unwrap();
+        let mut peer = engine.fork();
+        peer.insert_at(0, "ab").unwrap();
+        let update = peer
+            .export_from_version_vector(&engine.doc.oplog_vv().encode())
+            .unwrap();
+        let ops = peer.doc.len_ops();
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops - 1),
+        });
+        assert!(refused(engine.import_bytes(&update).map(drop)));
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops),
+        });
+        engine.import_bytes(&update).unwrap();
+        assert_eq!(engine.get_text(), "abx");
+    }
+
+    #[test]
+    fn test_every_mutator_refuses_while_detached() {
+        let mut engine = KernCore::with_content("first");
+        let frontier = engine.current_frontier();
+        engine.set_text("first\nsecond").unwrap();
+        engine.checkout(&frontier).unwrap();
+        assert!(engine.is_detached());
+
+        let detached = |result: Result<(), KernError>| matches!(result, Err(KernError::Detached));
+        assert!(detached(engine.set_text("x")));
+        assert!(detached(engine.insert_at(0, "x")));
+        assert!(detached(engine.delete_at(0, 1)));
+        assert!(detached(engine.mark_range(0, 1, "bold", true)));
+        assert!(detached(engine.unmark(0, 1, "bold")));
+        assert!(detached(engine.set_block_type(0, "heading")));
+        assert!(detached(engine.convert_line_endings("crlf")));
+        assert!(detached(engine.move_line(0, 0).map(drop)));
+        assert!(detached(engine.undo().map(drop)));
+        assert_eq!(engine.get_text(), "first");
+
+        engine.checkout_to_latest();
+        assert!(!engine.is_detached());
+        engine.mark_range(0, 1, "bold", true).unwrap();
+        engine.insert_at(0, "x").unwrap();
+        assert_eq!(engine.get_text(), "xfirst\nsecond");
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let mut engine = KernCore::new();
+        engine.set_text("draft one").unwrap();
+        engine.create_checkpoint("v1").unwrap();
+        engine.set_text("draft two").unwrap();
+        engine.create_checkpoint("v2").unwrap();
+        engine.set_text("draft three").unwrap();
+        engine.create_checkpoint("v3").unwrap();
+
+        // Checkpoints are bookkeeping, not undoable edits
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "draft two");
+        engine.redo().unwrap();
+
+        assert_eq!(engine.list_checkpoints(), vec!["v1", "v2", "v3"]);
+        engine.restore_checkpoint("v1").unwrap();
+        assert_eq!(engine.get_text(), "draft one");
+        assert!(matches!(
+            engine.set_text("nope"),
+            Err(KernError::Loro(_)) | Err(KernError::Detached)
+        ));
+
+        engine.checkout_to_latest();
+        assert_eq!(engine.get_text(), "draft three");
+        assert!(matches!(
+            engine.restore_checkpoint("missing"),
+            Err(KernError::UnknownCheckpoint(_))
+        ));
+    }
+
+    #[test]
+    fn test_subscribe_fires_on_edit() {
+        let mut engine = KernCore::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        engine.subscribe_lines(move |lines| sink.lock().unwrap().push(lines.to_vec()));
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 2,
+                col: 0,
+                insert: Some("Now ".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![vec![2]]);
+
+        engine.unsubscribe();
+        engine.set_text("ignored").unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_bold_mark_in_view() {
+        let mut engine = KernCore::new();
+        engine.set_text("plain\nsome bold text").unwrap();
+        // "bold" on line 1 starts at offset 6 + 5
+        engine.mark_range(11, 15, "bold", true).unwrap();
+
+        let view = engine.view();
+        assert!(view.lines[0].marks.is_none());
+        assert_eq!(
+            view.lines[1].marks,
+            Some(vec![MarkSpan {
+                start: 5,
+                end: 9,
+                key: "bold".into(),
+                value: LoroValue::Bool(true),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_line_ids_survive_insert_above() {
+        let mut engine = KernCore::new();
+        engine.set_text("first\nsecond\nthird").unwrap();
+        let before: Vec<String> = engine.view().lines.into_iter().map(|l| l.id).collect();
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 0,
+                insert: Some("new\n".into()),
+                delete: None,
+            })
+            .unwrap();
+
+        let after: Vec<String> = engine.view().lines.into_iter().map(|l| l.id).collect();
+        assert_eq!(after.len(), 4);
+        assert_eq!(&after[1..], &before[..]);
+        assert!(!before.contains(&after[0]));
+    }
+
+    #[test]
+    fn test_view_range_slices_lines() {
+        let mut engine = KernCore::new();
+        let content: Vec<String> = (0..100).map(|i| format!("line {i}")).collect();
+        engine.set_text(&content.join("\n")).unwrap();
+
+        let view = engine.view_range(10, 15);
+        let lines: Vec<&str> = view.lines.iter().map(|l| l.content.as_str()).collect();
+        assert_eq!(
+            lines,
+            ["line 10", "line 11", "line 12", "line 13", "line 14"]
+        );
+        assert_eq!(view.total_lines, 100);
+        assert_eq!(view.version, engine.get_version());
+
+        // Out-of-bounds ranges clamp instead of failing
+        assert_eq!(engine.view_range(98, 500).lines.len(), 2);
+        assert!(engine.view_range(200, 300).lines.is_empty());
+    }
+
+    #[test]
+    fn test_content_hash_ignores_history() {
+        let insert = |engine: &mut KernCore, line, col, text: &str| {
+            engine
+                .apply_delta(&EditDelta {
+                    line,
+                    col,
+                    insert: Some(text.into()),
+                    delete: None,
+                })
+                .unwrap();
+        };
+        let mut a = KernCore::with_content("");
+        insert(&mut a, 0, 0, "world");
+        insert(&mut a, 0, 0, "hello ");
+        a.mark_range(0, 5, "bold", true).unwrap();
+        let mut b = KernCore::with_content("held");
+        b.delete_at(2, 2).unwrap();
+        insert(&mut b, 0, 2, "llo world");
+        b.mark_range(0, 5, "bold", true).unwrap();
+
+        assert_eq!(a.get_text(), b.get_text());
+        assert_eq!(a.content_hash().unwrap(), b.content_hash().unwrap());
+        assert_eq!(a.content_hash().unwrap().len(), 32);
+
+        insert(&mut b, 0, 11, "!");
+        assert_ne!(a.content_hash().unwrap(), b.content_hash().unwrap());
+    }
+
+    #[test]
+    fn test_view_patch_contains_only_changes() {
+        let mut engine = KernCore::with_content("one\ntwo\nthree\nfour");
+        let cached = engine.view();
+        engine
+            .apply_delta(&EditDelta {
+                line: 1,
+                col: 3,
+                insert: Some("!".into()),
+                delete: None,
+            })
+            .unwrap();
+        engine.delete_at(9, 6).unwrap();
+
+        let patch = engine.view_patch(cached.version);
+        assert!(!patch.full && patch.lines.is_none());
+        let current = engine.view().lines;
+        assert_eq!(
+            patch.ops,
+            vec![
+                LineOp::Replace {
+                    index: 1,
+                    line: current[1].clone(),
+                },
+                LineOp::Delete { index: 2 },
+            ]
+        );
+
+        let mut lines = cached.lines;
+        for op in patch.ops {
+            match op {
+                LineOp::Insert { index, line } => lines.insert(index, line),
+                LineOp::Delete { index } => {
+                    lines.remove(index);
+                }
+                LineOp::Replace { index, line } => lines[index] = line,
+            }
+        }
+        assert_eq!(lines, current);
+
+        let stale = engine.view_patch(cached.version + 100);
+        assert!(stale.full && stale.ops.is_empty());
+        assert_eq!(stale.lines.unwrap(), current);
+    }
 
     #[test]
-    fn test_health_check() {
-        assert_eq!(check_health(), "Kern Engine: Active (Rust/WASM)");
+    fn test_view_cache_patches_single_line_edits() {
+        let mut engine = KernCore::new();
+        let content: Vec<String> = (0..10_000).map(|i| format!("line {i}")).collect();
+        engine.set_text(&content.join("\n")).unwrap();
+        engine.mark_range(0, 4, "bold", true).unwrap();
+        let cached_lines = |engine: &KernCore| {
+            engine
+                .view_cache
+                .borrow()
+                .as_ref()
+                .map(|c| c.lines.as_ptr())
+        };
+
+        let first = engine.view();
+        let buffer = cached_lines(&engine);
+        assert!(buffer.is_some());
+        engine.view();
+        assert_eq!(cached_lines(&engine), buffer);
+
+        // A same-line edit patches the cached line instead of re-splitting
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 2,
+                insert: Some("é".into()),
+                delete: None,
+            })
+            .unwrap();
+        let view = engine.view();
+        assert_eq!(cached_lines(&engine), buffer);
+        assert_eq!(view.lines[0].content, "liéne 0");
+        assert_eq!(view.lines[0].char_len, 7);
+        assert_eq!(view.lines[0].id, first.lines[0].id);
+        assert_eq!(view.lines[0].marks, engine.build_lines()[0].marks);
+
+        // Splitting a line forces a rebuild
+        engine
+            .apply_delta(&EditDelta {
+                line: 1,
+                col: 0,
+                insert: Some("\n".into()),
+                delete: None,
+            })
+            .unwrap();
+        let view = engine.view();
+        assert_eq!(view.total_lines, 10_001);
+        assert_eq!(view.lines[2].content, "line 1");
     }
 
     #[test]
-    fn test_engine_creation() {
-        let engine = KernEngine::new();
-        let content = engine.get_text();
-        assert!(content.contains("Welcome to Kern"));
+    fn test_block_type_follows_line() {
+        let mut engine = KernCore::new();
+        engine.set_text("Title\nbody").unwrap();
+        engine.set_block_type(0, "heading").unwrap();
+        assert_eq!(engine.view().lines[0].block, "heading");
+        assert_eq!(engine.view().lines[1].block, "paragraph");
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 0,
+                insert: Some("preface\n".into()),
+                delete: None,
+            })
+            .unwrap();
+        let view = engine.view();
+        assert_eq!(view.lines[0].block, "paragraph");
+        assert_eq!(view.lines[1].content, "Title");
+        assert_eq!(view.lines[1].block, "heading");
+
+        engine.set_block_type(1, "paragraph").unwrap();
+        assert_eq!(engine.view().lines[1].block, "paragraph");
+        assert!(matches!(
+            engine.set_block_type(0, "table"),
+            Err(KernError::InvalidBlockType(_))
+        ));
+    }
+
+    #[test]
+    fn test_view_line_metadata() {
+        let mut engine = KernCore::new();
+        engine.set_text("naïve 😀\nplain").unwrap();
+        engine.mark_range(0, 5, "bold", true).unwrap();
+
+        let view = engine.view();
+        assert_eq!(view.lines[0].char_len, "naïve 😀".chars().count());
+        assert_eq!(view.lines[0].char_len, 7);
+        assert!(view.lines[0].styled);
+        assert_eq!(view.lines[1].char_len, 5);
+        assert!(!view.lines[1].styled);
+    }
+
+    #[test]
+    fn test_marks_at_caret() {
+        let mut engine = KernCore::new();
+        engine.set_text("some bold text").unwrap();
+        engine.mark_range(5, 9, "bold", true).unwrap();
+        engine.mark_range(7, 9, "italic", true).unwrap();
+
+        let bold = BTreeMap::from([("bold".to_string(), LoroValue::Bool(true))]);
+        assert_eq!(engine.marks_at_caret(0, 7).unwrap(), bold);
+        assert_eq!(engine.marks_at_caret(0, 9).unwrap().len(), 2);
+        assert!(engine.marks_at_caret(0, 5).unwrap().is_empty());
+        assert!(engine.marks_at_caret(0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bold_mark_to_markdown() {
+        let mut engine = KernCore::new();
+        engine.set_text("some bold text\nnext").unwrap();
+        engine.mark_range(5, 9, "bold", true).unwrap();
+
+        assert_eq!(engine.to_markdown(), "some **bold** text\nnext");
+        assert_eq!(engine.to_plain_text(), "some bold text\nnext");
+    }
+
+    #[test]
+    fn test_named_containers_are_independent() {
+        let mut engine = KernCore::new();
+        let title = EditDelta {
+            line: 0,
+            col: 0,
+            insert: Some("My Title".into()),
+            delete: None,
+        };
+        let body = EditDelta {
+            line: 0,
+            col: 0,
+            insert: Some("Body text".into()),
+            delete: None,
+        };
+        engine.apply_delta_to("title", &title).unwrap();
+        engine.apply_delta_to("body", &body).unwrap();
+
+        assert_eq!(engine.get_text_of("title"), "My Title");
+        assert_eq!(engine.get_text_of("body"), "Body text");
+        assert!(engine.get_text().starts_with("# Welcome"));
+        assert_eq!(engine.list_containers(), vec!["body", "content", "title"]);
+    }
+
+    #[test]
+    fn test_custom_container_name() {
+        let mut engine = KernCore::with_container_name("notes");
+        engine.set_text("first\nsecond").unwrap();
+        engine
+            .apply_delta(&EditDelta {
+                line: 1,
+                col: 6,
+                insert: Some("!".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert_eq!(engine.get_text_of("notes"), "first\nsecond!");
+        assert_eq!(engine.get_text_of(DEFAULT_CONTAINER), "");
+        assert_eq!(engine.view().lines[1].content, "second!");
+
+        let snapshot = engine.export_snapshot().unwrap();
+        let mut peer = KernCore::with_container_name("notes");
+        peer.import_bytes(&snapshot).unwrap();
+        assert_eq!(peer.get_text(), "first\nsecond!");
+        let mut other = KernCore::with_content("");
+        other.import_bytes(&snapshot).unwrap();
+        assert_eq!(other.get_text(), "");
+    }
+
+    #[test]
+    fn test_cursor_survives_insert_above() {
+        let mut engine = KernCore::new();
+        engine.set_text("hello\nworld").unwrap();
+        let cursor = engine.cursor_from_pos(1, 2).unwrap();
+
+        engine
+            .apply_delta(&EditDelta {
+                line: 0,
+                col: 0,
+                insert: Some("new line\n".into()),
+                delete: None,
+            })
+            .unwrap();
+        engine
+            .apply_delta(&EditDelta {
+                line: 2,
+                col: 0,
+                insert: Some(">> ".into()),
+                delete: None,
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine.resolve_cursor(&cursor).unwrap(),
+            Position { line: 2, col: 5 }
+        );
+    }
+
+    #[test]
+    fn test_awareness_exchange() {
+        let mut a = KernCore::new();
+        let mut b = KernCore::new();
+        a.set_peer_id("1").unwrap();
+        b.set_peer_id("2").unwrap();
+        a.set_local_presence("alice");
+        b.set_local_presence("bob");
+
+        b.merge_awareness(&a.encode_awareness()).unwrap();
+        let states = a.merge_awareness(&b.encode_awareness()).unwrap();
+
+        let expected = vec![
+            PeerState {
+                peer: "1".into(),
+                state: "alice".into(),
+            },
+            PeerState {
+                peer: "2".into(),
+                state: "bob".into(),
+            },
+        ];
+        assert_eq!(states, expected);
+        assert_eq!(b.peer_states(), expected);
+    }
+
+    #[test]
+    fn test_peer_selection_tracks_edits() {
+        let mut a = KernCore::with_content("hello world");
+        a.set_peer_id("1").unwrap();
+        let mut b = a.fork();
+        b.set_peer_id("2").unwrap();
+        a.set_local_presence("alice");
+
+        let anchor = b.cursor_from_pos(0, 6).unwrap();
+        let head = b.cursor_from_pos(0, 10).unwrap();
+        b.set_local_selection(&anchor, &head).unwrap();
+        a.merge_awareness(&b.encode_awareness()).unwrap();
+
+        a.insert_at(0, "oh, ").unwrap();
+        let selection = PeerSelection {
+            peer: "2".into(),
+            anchor_pos: Position { line: 0, col: 10 },
+            head_pos: Position { line: 0, col: 14 },
+        };
+        assert_eq!(a.peer_selections(), vec![selection]);
+        // Selections don't show up as presence states
+        let peers: Vec<String> = a.peer_states().into_iter().map(|s| s.peer).collect();
+        assert_eq!(peers, vec!["1"]);
+
+        // A fresh replica, since updates within the same millisecond are dropped
+        let mut c = a.fork();
+        b.set_local_selection(&head, &head).unwrap();
+        c.merge_awareness(&b.encode_awareness()).unwrap();
+        let caret = &c.peer_selections()[0];
+        assert_eq!(caret.anchor_pos, Position { line: 0, col: 14 });
+        assert_eq!(caret.head_pos, caret.anchor_pos);
+    }
+
+    #[test]
+    fn test_get_line() {
+        let mut engine = KernCore::new();
+        engine.set_text("first\nmiddle\nlast").unwrap();
+
+        assert_eq!(engine.get_line_count(), 3);
+        assert_eq!(engine.get_line(0).as_deref(), Some("first"));
+        assert_eq!(engine.get_line(2).as_deref(), Some("last"));
+        assert_eq!(engine.get_line(3), None);
+    }
+
+    #[test]
+    fn test_search_across_lines() {
+        let mut engine = KernCore::new();
+        engine.set_text("😀 cat catcat\nno match\ncat").unwrap();
+
+        let at = |line, col| SearchMatch {
+            line,
+            col,
+            length: 3,
+        };
+        assert_eq!(
+            engine.find("cat", true),
+            vec![at(0, 2), at(0, 6), at(0, 9), at(2, 0)]
+        );
+        assert!(engine.find("", true).is_empty());
+    }
+
+    #[test]
+    fn test_search_case_insensitive() {
+        let mut engine = KernCore::new();
+        engine.set_text("Kern kern KERN").unwrap();
+
+        assert_eq!(engine.find("kern", true).len(), 1);
+        let cols: Vec<usize> = engine.find("kern", false).iter().map(|m| m.col).collect();
+        assert_eq!(cols, vec![0, 5, 10]);
+    }
+
+    #[test]
+    fn test_stats_empty() {
+        let stats = TextStats::of("");
+        assert_eq!(
+            stats,
+            TextStats {
+                lines: 1,
+                words: 0,
+                chars: 0,
+                bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_stats_multi_line() {
+        let mut engine = KernCore::new();
+        engine.set_text("# Title\n\nsome  words\there").unwrap();
+        let stats = TextStats::of(&engine.get_text());
+        assert_eq!(stats.lines, 3);
+        assert_eq!(stats.words, 5);
+        assert_eq!(stats.chars, stats.bytes);
+
+        engine.set_text("a\n").unwrap();
+        assert_eq!(engine.stats().lines, engine.get_line_count());
+    }
+
+    #[test]
+    fn test_stats_emoji_chars_vs_bytes() {
+        let stats = TextStats::of("hi 😀");
+        assert_eq!(stats.words, 2);
+        assert_eq!(stats.chars, 4);
+        assert_eq!(stats.bytes, 7);
+    }
+
+    #[test]
+    fn test_oplog_stats_and_compaction() {
+        let mut engine = KernCore::with_content("");
+        let before = engine.oplog_stats();
+        assert!(!engine.should_compact());
+
+        let filler = "x".repeat(COMPACT_MIN_OPS);
+        engine.set_text(&filler).unwrap();
+        let filled = engine.oplog_stats();
+        assert!(filled.ops > before.ops);
+        assert!(filled.estimated_bytes > before.estimated_bytes);
+        assert_eq!(filled.frontier_count, 1);
+        // Lots of ops, but all of them still live
+        assert!(!engine.should_compact());
+
+        engine.set_text("short").unwrap();
+        assert!(engine.oplog_stats().ops > filled.ops);
+        assert!(engine.should_compact());
+    }
+
+    #[test]
+    fn test_compact_keeps_content_and_drops_history() {
+        let mut engine = KernCore::with_content("");
+        for i in 0..200 {
+            engine.insert_at(i, "x").unwrap();
+        }
+        engine.set_text("hello world").unwrap();
+        engine.mark_range(0, 5, "bold", true).unwrap();
+        let before = engine.oplog_stats();
+        let peer = engine.get_peer_id();
+
+        engine.compact().unwrap();
+        assert_eq!(engine.get_text(), "hello world");
+        assert_eq!(
+            engine.marks_at_caret(0, 3).unwrap().get("bold"),
+            Some(&LoroValue::Bool(true))
+        );
+        assert!(engine.oplog_stats().ops * 10 < before.ops);
+        assert_ne!(engine.get_peer_id(), peer);
+        assert!(!engine.can_undo());
+
+        // Still editable afterwards
+        engine.insert_at(11, "!").unwrap();
+        assert_eq!(engine.get_text(), "hello world!");
+    }
+
+    #[test]
+    fn test_edit_caret_after_single_char_insert() {
+        let mut engine = KernCore::with_content("ab\ncd");
+        let result = engine
+            .apply_delta_with_caret(
+                &EditDelta {
+                    line: 1,
+                    col: 1,
+                    insert: Some("x".to_string()),
+                    delete: None,
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            result.caret,
+            Caret {
+                line: 1,
+                col: 2,
+                offset: 5
+            }
+        );
+        assert_eq!(result.lines.start, 1);
+    }
+
+    #[test]
+    fn test_edit_caret_after_multi_line_insert() {
+        let mut engine = KernCore::with_content("ab\ncd");
+        let result = engine
+            .apply_delta_with_caret(
+                &EditDelta {
+                    line: 0,
+                    col: 1,
+                    insert: Some("1\n22\n333".to_string()),
+                    delete: None,
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(engine.get_text(), "a1\n22\n333b\ncd");
+        assert_eq!(
+            result.caret,
+            Caret {
+                line: 2,
+                col: 3,
+                offset: 9
+            }
+        );
+    }
+
+    #[test]
+    fn test_edit_caret_after_delete() {
+        let mut engine = KernCore::with_content("ab\ncd");
+        let edit = EditDelta {
+            line: 1,
+            col: 1,
+            insert: None,
+            delete: Some(3),
+        };
+        let result = engine.apply_delta_with_caret(&edit, None).unwrap();
+        assert_eq!(engine.get_text(), "ad");
+        assert_eq!(
+            result.caret,
+            Caret {
+                line: 0,
+                col: 1,
+                offset: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_engines_are_independent() {
+        let mut a = KernCore::with_content("shared start");
+        let mut b = KernCore::with_content("shared start");
+        assert_ne!(a.get_peer_id(), b.get_peer_id());
+
+        let calls = Arc::new(Mutex::new(0));
+        let seen = calls.clone();
+        a.subscribe_lines(move |_| *seen.lock().unwrap() += 1);
+
+        b.insert_at(0, "b: ").unwrap();
+        b.insert_at(0, "b: ").unwrap();
+        assert_eq!(a.get_text(), "shared start");
+        assert_eq!(*calls.lock().unwrap(), 0);
+
+        a.insert_at(0, "a: ").unwrap();
+        assert_eq!(a.get_text(), "a: shared start");
+        assert_eq!(b.get_text(), "b: b: shared start");
+        assert_eq!(*calls.lock().unwrap(), 1);
+        assert_eq!(a.get_version() + 1, b.get_version());
+    }
+
+    #[test]
+    fn test_json_round_trip_preserves_view() {
+        let mut engine = KernCore::with_content("Title\nsome bold text\n- item");
+        engine.set_block_type(0, "heading").unwrap();
+        engine.set_block_type(2, "list-item").unwrap();
+        engine.mark_range(11, 15, "bold", true).unwrap();
+        let json = engine.to_json();
+
+        // Through serde, as a server would store it
+        let stored = serde_json::to_string(&json).unwrap();
+        let mut restored = KernCore::with_content("old content");
+        restored
+            .from_json(&serde_json::from_str(&stored).unwrap())
+            .unwrap();
+
+        let strip_ids = |view: DocumentView| -> Vec<_> {
+            view.lines
+                .into_iter()
+                .map(|line| (line.content, line.marks, line.block))
+                .collect()
+        };
+        assert_eq!(strip_ids(restored.view()), strip_ids(engine.view()));
+        assert!(restored.get_version() >= engine.get_version());
+    }
+
+    #[test]
+    fn test_from_json_rejects_bad_marks() {
+        let mut engine = KernCore::with_content("keep");
+        let json = DocumentJson {
+            lines: vec![JsonLine {
+                content: "ab".into(),
+                marks: vec![MarkSpan {
+                    start: 1,
+                    end: 5,
+                    key: "bold".into(),
+                    value: true.into(),
+                }],
+                block: default_block(),
+            }],
+            version: 0,
+        };
+        assert!(matches!(
+            engine.from_json(&json),
+            Err(KernError::OutOfRange(_))
+        ));
+        assert_eq!(engine.get_text(), "keep");
+    }
+
+    #[test]
+    fn test_find_interleavings_flags_concurrent_line_edits() {
+        let mut a = KernCore::with_content("hello world\nsecond");
+        let since = a.current_frontier();
+        let mut b = a.fork();
+
+        a.insert_at(5, " there").unwrap();
+        a.insert_at(24, "!").unwrap();
+        b.insert_at(11, " again").unwrap();
+        a.import_bytes(&b.export_updates().unwrap()).unwrap();
+        assert_eq!(a.get_text(), "hello there world again\nsecond!");
+
+        // b edits line 1 after seeing a's edit there: sequential, not flagged
+        b.import_bytes(&a.export_updates().unwrap()).unwrap();
+        b.insert_at(24, "2").unwrap();
+        a.import_bytes(&b.export_updates().unwrap()).unwrap();
+
+        let mut peers = vec![a.get_peer_id(), b.get_peer_id()];
+        peers.sort();
+        assert_eq!(
+            a.find_interleavings(&since).unwrap(),
+            vec![Interleaving { line: 0, peers }]
+        );
+    }
+
+    #[test]
+    fn test_chunked_export_matches_single_shot() {
+        let engine = KernCore::with_content(&"some text\n".repeat(50));
+        let whole = engine.export_snapshot().unwrap();
+        let exported = engine.export_snapshot_chunked(64).unwrap();
+
+        assert_eq!(exported.total_len, whole.len());
+        assert!(exported.chunks.len() > 1);
+        assert!(exported.chunks.iter().all(|chunk| chunk.len() <= 64));
+        assert_eq!(exported.chunks.concat(), whole);
+        assert!(engine.export_snapshot_chunked(0).is_err());
+    }
+
+    #[test]
+    fn test_readonly_blocks_edits_but_not_imports() {
+        let mut engine = KernCore::with_content("view me");
+        let mut remote = engine.fork();
+        engine.set_readonly(true);
+        assert!(engine.is_readonly());
+
+        assert!(matches!(engine.insert_at(0, "x"), Err(KernError::ReadOnly)));
+        assert!(matches!(
+            engine.set_block_type(0, "heading"),
+            Err(KernError::ReadOnly)
+        ));
+        assert_eq!(engine.undo().unwrap_err().code(), "READ_ONLY");
+
+        remote.insert_at(7, "!").unwrap();
+        engine
+            .import_bytes(&remote.export_updates().unwrap())
+            .unwrap();
+        assert_eq!(engine.get_text(), "view me!");
+
+        engine.set_readonly(false);
+        engine.insert_at(0, "> ").unwrap();
+        assert_eq!(engine.get_text(), "> view me!");
+    }
+
+    #[test]
+    fn test_grapheme_columns_with_combining_marks() {
+        // "e" + combining acute, twice
+        let engine = KernCore::with_content("x\ne\u{301}e\u{301}!");
+        assert_eq!(engine.grapheme_len(1).unwrap(), 3);
+        assert_eq!(engine.view().lines[1].char_len, 5);
+
+        assert_eq!(engine.grapheme_offset_of(1, 2).unwrap(), 6);
+        assert_eq!(engine.offset_of(1, 2).unwrap(), 4);
+        assert_eq!(
+            engine.grapheme_position_of(6).unwrap(),
+            Position { line: 1, col: 2 }
+        );
+        // Between a letter and its accent snaps back to the letter
+        assert_eq!(
+            engine.grapheme_position_of(3).unwrap(),
+            Position { line: 1, col: 0 }
+        );
+    }
+
+    #[test]
+    fn test_grapheme_columns_with_emoji_sequences() {
+        // A flag and a family emoji, four and five characters each
+        let engine = KernCore::with_content(
+            "\u{1F1E9}\u{1F1EA}a\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}",
+        );
+        assert_eq!(engine.grapheme_len(0).unwrap(), 3);
+        assert_eq!(engine.view().lines[0].char_len, 8);
+        assert_eq!(engine.grapheme_offset_of(0, 2).unwrap(), 3);
+        assert_eq!(engine.grapheme_offset_of(0, 9).unwrap(), 8);
+        assert_eq!(
+            engine.grapheme_position_of(8).unwrap(),
+            Position { line: 0, col: 3 }
+        );
+    }
+
+    #[test]
+    fn test_created_at_and_revisions_survive_reload() {
+        let mut engine = KernCore::with_content("draft");
+        let created = engine.created_at().unwrap();
+        assert!(created > 0);
+        assert_eq!(engine.revision_count(), 0);
+
+        engine.insert_at(5, " one").unwrap();
+        engine.insert_at(9, " two").unwrap();
+        engine.undo().unwrap();
+        assert_eq!(engine.revision_count(), 3);
+
+        let mut loaded = KernCore::with_content("");
+        loaded
+            .import_bytes(&engine.export_snapshot().unwrap())
+            .unwrap();
+        assert_eq!(loaded.revision_count(), 3);
+        assert_eq!(loaded.created_at(), Some(created));
+
+        // Merging keeps the larger count
+        let mut other = loaded.fork();
+        other.insert_at(0, "> ").unwrap();
+        engine.insert_at(0, "# ").unwrap();
+        engine.insert_at(0, "# ").unwrap();
+        other
+            .import_bytes(&engine.export_updates().unwrap())
+            .unwrap();
+        assert_eq!(other.revision_count(), 5);
+    }
+
+    #[test]
+    fn test_held_edits_count_as_one_revision() {
+        let mut engine = KernCore::with_content("");
+        engine.begin_batch();
+        for _ in 0..3 {
+            engine.insert_at(0, "a").unwrap();
+        }
+        engine.end_batch();
+        assert_eq!(engine.revision_count(), 1);
+
+        engine.set_mode(CommitMode::Debounced { ops: 5 });
+        for _ in 0..5 {
+            engine.insert_at(0, "b").unwrap();
+        }
+        assert_eq!(engine.revision_count(), 2);
+        // Nothing held, so nothing to count
+        engine.flush();
+        assert_eq!(engine.revision_count(), 2);
+
+        let mut loaded = KernCore::with_content("");
+        loaded
+            .import_bytes(&engine.export_snapshot().unwrap())
+            .unwrap();
+        assert_eq!(loaded.revision_count(), 2);
+    }
+
+    #[test]
+    fn test_merged_revision_count_is_the_same_for_every_loader() {
+        let mut a = KernCore::with_content("");
+        a.set_peer_id("1").unwrap();
+        let mut b = a.fork();
+        b.set_peer_id("2").unwrap();
+        for _ in 0..10 {
+            a.insert_at(0, "a").unwrap();
+        }
+        b.insert_at(0, &"b".repeat(50)).unwrap();
+
+        a.import_bytes(&b.export_updates().unwrap()).unwrap();
+        assert_eq!(a.revision_count(), 10);
+        let mut loaded = KernCore::with_content("");
+        loaded.import_bytes(&a.export_snapshot().unwrap()).unwrap();
+        assert_eq!(loaded.revision_count(), 10);
+    }
+
+    #[test]
+    fn test_insert_at_line_count_appends_a_line() {
+        let mut engine = KernCore::with_content("first\nsecond");
+        let line_count = engine.get_line_count();
+        let affected = engine
+            .apply_delta(&EditDelta {
+                line: line_count,
+                col: 0,
+                insert: Some("third".into()),
+                delete: None,
+            })
+            .unwrap();
+        assert_eq!(engine.get_text(), "first\nsecond\nthird");
+        assert_eq!(affected.end, 3);
+
+        // Editing past the appended position is still out of range
+        let edit = EditDelta {
+            line: 4,
+            col: 0,
+            insert: Some("x".into()),
+            delete: None,
+        };
+        assert!(matches!(
+            engine.apply_delta(&edit),
+            Err(KernError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_external_reformat_reports_hunks_and_undoes_at_once() {
+        let original = "fn main() {\nlet a = 1;\nlet b = 2;\n}\n\nfn other() {\nx();\n}";
+        let formatted =
+            "fn main() {\n    let a = 1;\n    let b = 2;\n}\n\nfn other() {\n    x();\n}";
+        let mut engine = KernCore::with_content("");
+        engine.set_text(original).unwrap();
+        let cursor = engine.cursor_from_pos(3, 1).unwrap();
+
+        let hunks = engine.apply_external_text(formatted).unwrap();
+        assert_eq!(engine.get_text(), formatted);
+        assert_eq!(
+            hunks,
+            vec![
+                LineRange {
+                    start: 1,
+                    end: 3,
+                    inserted: 2,
+                    removed: 2
+                },
+                LineRange {
+                    start: 6,
+                    end: 7,
+                    inserted: 1,
+                    removed: 1
+                },
+            ]
+        );
+        assert_eq!(
+            engine.resolve_cursor(&cursor).unwrap(),
+            Position { line: 3, col: 1 }
+        );
+        assert!(engine.apply_external_text(formatted).unwrap().is_empty());
+
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), original);
+    }
+
+    #[test]
+    fn test_pending_dependencies_name_the_missing_update() {
+        let mut a = KernCore::with_content("");
+        let mut b = a.fork();
+        a.insert_at(0, "first").unwrap();
+        let first = a.export_updates().unwrap();
+        a.insert_at(0, "second ").unwrap();
+        let second = a.export_updates().unwrap();
+        assert!(b.pending_dependencies().is_empty());
+
+        b.import_bytes(&second).unwrap();
+        // "first" plus its version clock, revision count and creation time
+        assert_eq!(
+            b.pending_dependencies(),
+            vec![PendingDependency {
+                peer: a.get_peer_id(),
+                start: 0,
+                end: 8,
+            }]
+        );
+
+        b.import_bytes(&first).unwrap();
+        assert!(b.pending_dependencies().is_empty());
+        assert_eq!(b.get_text(), "second first");
+    }
+
+    #[test]
+    fn test_plain_container_refuses_marks() {
+        let mut title = KernCore::with_container("title", false);
+        title.insert_at(0, "Report").unwrap();
+        assert!(matches!(
+            title.mark_range(0, 6, "bold", true),
+            Err(KernError::PlainContainer(name)) if name == "title"
+        ));
+        assert_eq!(
+            title.unmark(0, 6, "bold").unwrap_err().code(),
+            "PLAIN_CONTAINER"
+        );
+        assert!(title.view().lines[0].marks.is_none());
+
+        let mut body = KernCore::with_container("body", true);
+        body.insert_at(0, "Report").unwrap();
+        body.mark_range(0, 6, "bold", true).unwrap();
+        assert!(body.view().lines[0].styled);
+    }
+
+    #[test]
+    fn test_import_timed_reports_size_and_ops() {
+        let source = KernCore::with_content(&"line of text\n".repeat(100));
+        let snapshot = source.export_snapshot().unwrap();
+
+        let mut engine = KernCore::with_content("");
+        let metrics = engine.import_timed(&snapshot).unwrap();
+        assert_eq!(metrics.bytes, snapshot.len());
+        assert!(metrics.applied_ops > 0);
+        assert!(metrics.duration_ms >= 0.0);
+        assert_eq!(engine.get_text(), source.get_text());
+    }
+
+    #[test]
+    fn test_redundant_import_keeps_version() {
+        let source = KernCore::with_content("same\nsnapshot");
+        let snapshot = source.export_snapshot().unwrap();
+        let mut engine = KernCore::with_content("");
+        let calls = Rc::new(RefCell::new(0));
+        let seen = calls.clone();
+        engine.on_sync_with(move |_, _| *seen.borrow_mut() += 1);
+
+        engine.import_bytes(&snapshot).unwrap();
+        let version = engine.get_version();
+        let affected = engine.import_bytes(&snapshot).unwrap();
+        assert_eq!(engine.get_version(), version);
+        assert_eq!(affected, LineRange::default());
+        assert_eq!(*calls.borrow(), 1);
+    }
+
+    #[test]
+    fn test_word_range_at_selects_word_or_whitespace() {
+        let engine = KernCore::with_content("title\nsay héllo,   world");
+        let range = |col| engine.word_range_at(1, col).unwrap();
+        let span = |start_col, end_col| ColumnRange { start_col, end_col };
+
+        assert_eq!(range(6), span(4, 9));
+        assert_eq!(range(4), span(4, 9));
+        assert_eq!(range(9), span(9, 10));
+        assert_eq!(range(11), span(10, 13));
+        // Past the last character, the word before the caret
+        assert_eq!(range(99), span(13, 18));
+    }
+
+    #[test]
+    fn test_line_range_at_spans_the_line() {
+        let engine = KernCore::with_content("first\nsécond line\n");
+        assert_eq!(
+            engine.line_range_at(1).unwrap(),
+            ColumnRange {
+                start_col: 0,
+                end_col: 11
+            }
+        );
+        assert_eq!(engine.line_range_at(2).unwrap().end_col, 0);
+        assert!(engine.line_range_at(3).is_err());
+    }
+
+    #[test]
+    fn test_subscribe_deltas_reports_char_offsets() {
+        let mut engine = KernCore::with_content("hello\nworld");
+        let deltas = Arc::new(Mutex::new(Vec::new()));
+        let seen = deltas.clone();
+        engine.subscribe_deltas(move |delta| seen.lock().unwrap().push(delta.to_vec()));
+
+        engine.insert_at(8, "X").unwrap();
+        assert_eq!(
+            deltas.lock().unwrap().pop(),
+            Some(vec![DeltaOp::Retain(8), DeltaOp::Insert("X".into())])
+        );
+
+        // Remote changes come through too
+        let mut remote = engine.fork();
+        remote.delete_at(0, 2).unwrap();
+        engine
+            .import_bytes(&remote.export_updates().unwrap())
+            .unwrap();
+        assert_eq!(deltas.lock().unwrap().pop(), Some(vec![DeltaOp::Delete(2)]));
+    }
+
+    #[test]
+    fn test_clear_container_leaves_others_untouched() {
+        let mut engine = KernCore::with_content("# Body\ntext");
+        engine.set_block_type(0, "heading").unwrap();
+        let edit = EditDelta {
+            line: 0,
+            col: 0,
+            insert: Some("Draft".into()),
+            delete: None,
+        };
+        engine.apply_delta_to("title", &edit).unwrap();
+
+        engine.clear_container("title").unwrap();
+        assert_eq!(engine.get_text_of("title"), "");
+        assert_eq!(engine.get_text(), "# Body\ntext");
+
+        engine.clear_container(DEFAULT_CONTAINER).unwrap();
+        let view = engine.view();
+        assert_eq!(view.lines.len(), 1);
+        assert_eq!(view.lines[0].block, "paragraph");
+        assert!(matches!(
+            engine.clear_container("missing"),
+            Err(KernError::UnknownContainer(_))
+        ));
+
+        engine.apply_delta_to("scratch", &edit).unwrap();
+        engine.remove_container("scratch").unwrap();
+        let LoroValue::Map(roots) = engine.doc.get_deep_value() else {
+            panic!("expected a map");
+        };
+        assert!(!roots.contains_key("scratch"));
+        assert!(roots.contains_key("title"));
+    }
+
+    #[test]
+    fn test_diff_against_checkpoint() {
+        let mut engine = KernCore::with_content("one\ntwo\nthree\nfour");
+        engine.create_checkpoint("v1").unwrap();
+        engine.set_text("one\n2\nthree\nfour\nfive").unwrap();
+
+        let changes = engine.diff_against_checkpoint("v1").unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                LineChange {
+                    line: 1,
+                    kind: LineChangeKind::Modified,
+                },
+                LineChange {
+                    line: 4,
+                    kind: LineChangeKind::Removed,
+                },
+            ]
+        );
+        // Previewing doesn't check out
+        assert_eq!(engine.get_text(), "one\n2\nthree\nfour\nfive");
+        assert!(matches!(
+            engine.diff_against_checkpoint("missing"),
+            Err(KernError::UnknownCheckpoint(_))
+        ));
+    }
+
+    #[test]
+    fn test_autosave_due_until_marked_saved() {
+        let mut engine = KernCore::new();
+        assert!(!engine.autosave_due(0));
+
+        let mut edits = 0;
+        while !engine.autosave_due(200) {
+            let edit = EditDelta {
+                line: 0,
+                col: edits,
+                insert: Some("x".into()),
+                delete: None,
+            };
+            engine.apply_delta(&edit).unwrap();
+            edits += 1;
+            assert!(edits < 100, "autosave never became due");
+        }
+        assert!(edits > 1);
+
+        engine.mark_saved();
+        assert!(!engine.autosave_due(1));
+        engine.set_text("changed").unwrap();
+        assert!(engine.autosave_due(1));
+    }
+
+    #[test]
+    fn test_deep_value_dumps_every_container() {
+        let mut engine = KernCore::with_content("hello");
+        engine.set_text("hello world").unwrap();
+        engine.create_checkpoint("v1").unwrap();
+
+        let LoroValue::Map(roots) = engine.deep_value() else {
+            panic!("expected a map");
+        };
+        assert_eq!(
+            roots.get(DEFAULT_CONTAINER),
+            Some(&LoroValue::from("hello world"))
+        );
+        let Some(LoroValue::Map(meta)) = roots.get(META) else {
+            panic!("expected the metadata map");
+        };
+        assert!(meta.contains_key(VERSION_KEY));
+        assert!(matches!(roots.get(CHECKPOINTS), Some(LoroValue::Map(_))));
+    }
+
+    #[test]
+    fn test_enter_at_line_end_keeps_block_type() {
+        let enter = |line, col, insert: &str| EditDelta {
+            line,
+            col,
+            insert: Some(insert.into()),
+            delete: None,
+        };
+        let blocks = |engine: &KernCore| -> Vec<(String, String)> {
+            engine
+                .view()
+                .lines
+                .into_iter()
+                .map(|line| (line.content, line.block))
+                .collect()
+        };
+        let pair = |content: &str, block: &str| (content.to_string(), block.to_string());
+
+        let mut engine = KernCore::with_content("Title");
+        engine.set_block_type(0, "heading").unwrap();
+        engine.apply_delta(&enter(0, 5, "\n")).unwrap();
+        assert_eq!(
+            blocks(&engine),
+            [pair("Title", "heading"), pair("", "paragraph")]
+        );
+        engine.apply_delta(&enter(1, 0, "body")).unwrap();
+        engine.apply_delta(&enter(0, 5, "\n")).unwrap();
+        assert_eq!(
+            blocks(&engine),
+            [
+                pair("Title", "heading"),
+                pair("", "paragraph"),
+                pair("body", "paragraph")
+            ]
+        );
+
+        // Text typed with the break stays on the heading's line
+        let mut engine = KernCore::with_content("Title\nbody");
+        engine.set_block_type(0, "heading").unwrap();
+        engine.apply_delta(&enter(0, 5, "!\nmore")).unwrap();
+        assert_eq!(engine.get_text(), "Title!\nmore\nbody");
+        assert_eq!(
+            blocks(&engine),
+            [
+                pair("Title!", "heading"),
+                pair("more", "paragraph"),
+                pair("body", "paragraph")
+            ]
+        );
+        // Breaking at the start still carries the heading down with its text
+        engine.apply_delta(&enter(0, 0, "\n")).unwrap();
+        assert_eq!(
+            blocks(&engine)[..2],
+            [pair("", "paragraph"), pair("Title!", "heading")]
+        );
+        // A CRLF line keeps its type across a CRLF break too
+        engine.convert_line_endings("crlf").unwrap();
+        engine.apply_delta(&enter(1, 6, "\r\n")).unwrap();
+        assert_eq!(engine.get_text(), "\r\nTitle!\r\n\r\nmore\r\nbody");
+        assert_eq!(
+            blocks(&engine)[1..3],
+            [pair("Title!\r", "heading"), pair("\r", "paragraph")]
+        );
+
+        // Moving the last line's type costs a key insert and delete
+        let mut engine = KernCore::with_content("Title");
+        engine.set_block_type(0, "heading").unwrap();
+        let ops = engine.doc.len_ops() + 2;
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops + 2),
+        });
+        assert!(engine.apply_delta(&enter(0, 5, "\n")).is_err());
+        engine.set_limits(Limits {
+            max_chars: None,
+            max_ops: Some(ops + 3),
+        });
+        engine.apply_delta(&enter(0, 5, "\n")).unwrap();
+        assert_eq!(engine.doc.len_ops(), ops + 3);
+        assert_eq!(blocks(&engine)[0], pair("Title", "heading"));
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let mut engine = KernCore::new();
+        engine.set_text("alpha\nbeta\ngamma").unwrap();
+        let old = engine.export_snapshot().unwrap();
+        engine.set_text("alpha\nBETA\ngamma\ndelta").unwrap();
+        let new = engine.export_snapshot().unwrap();
+
+        assert_eq!(
+            snapshot_diff(&old, &new).unwrap(),
+            vec![
+                LineChange {
+                    line: 1,
+                    kind: LineChangeKind::Modified,
+                },
+                LineChange {
+                    line: 3,
+                    kind: LineChangeKind::Added,
+                },
+            ]
+        );
+
+        let removed = snapshot_diff(&old, &[]).unwrap();
+        assert_eq!(removed.len(), 3);
+        assert!(removed.iter().all(|c| c.kind == LineChangeKind::Removed));
+    }
+
+    #[test]
+    fn test_set_text_diffed_touches_only_changed_region() {
+        let mut engine = KernCore::new();
+        engine
+            .set_text("the quick brown fox\njumps over\nthe lazy dog")
+            .unwrap();
+        let cursor = engine.cursor_from_pos(2, 4).unwrap();
+        let ops_before = engine.doc.len_ops();
+
+        engine
+            .set_text_diffed("the slow brown fox\njumps over\nthe lazy dog")
+            .unwrap();
+
+        assert_eq!(
+            engine.get_text(),
+            "the slow brown fox\njumps over\nthe lazy dog"
+        );
+        // At most "quick" deleted, "slow" inserted, the version clock and the
+        // revision count
+        assert!(engine.doc.len_ops() - ops_before <= 11);
+        // Text after the edit kept its identity, so cursors follow it
+        assert_eq!(
+            engine.resolve_cursor(&cursor).unwrap(),
+            Position { line: 2, col: 4 }
+        );
+    }
+
+    #[test]
+    fn test_diff_ops_edit_script() {
+        let ops = diff_ops(&['a', 'b', 'c', 'd'], &['a', 'x', 'c', 'd', 'e']);
+        let deletes = ops.iter().filter(|op| **op == DiffOp::Delete).count();
+        let inserts = ops.iter().filter(|op| **op == DiffOp::Insert).count();
+        assert_eq!((deletes, inserts), (1, 2));
+        assert_eq!(ops.len(), 6);
+    }
+
+    #[test]
+    fn test_replace_all_is_one_undo_step() {
+        let mut engine = KernCore::new();
+        engine.set_text("one two one\none").unwrap();
+
+        assert_eq!(engine.replace_all("one", "three", true).unwrap(), 3);
+        assert_eq!(engine.get_text(), "three two three\nthree");
+        assert_eq!(engine.replace_all("THREE", "3", false).unwrap(), 3);
+        assert_eq!(engine.get_text(), "3 two 3\n3");
+        assert_eq!(engine.replace_all("missing", "x", true).unwrap(), 0);
+
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "three two three\nthree");
+        engine.undo().unwrap();
+        assert_eq!(engine.get_text(), "one two one\none");
     }
 }
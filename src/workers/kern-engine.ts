@@ -10,9 +10,17 @@ import type {
   WorkerResponse,
   DocumentView,
   EditDelta,
+  EditResult,
 } from './kern-engine.worker'
 
-export type { DocumentView, EditDelta, LineView } from './kern-engine.worker'
+export type {
+  Caret,
+  DocumentView,
+  EditDelta,
+  EditResult,
+  LineRange,
+  LineView,
+} from './kern-engine.worker'
 
 type MessageHandler = (response: WorkerResponse) => void
 
@@ -160,11 +168,11 @@ class KernEngineAPI {
   /**
    * Apply an edit to the document
    */
-  async applyEdit(delta: EditDelta): Promise<number[]> {
+  async applyEdit(delta: EditDelta): Promise<EditResult> {
     return new Promise((resolve, reject) => {
       this.once('edited', (response) => {
         if (response.type === 'edited') {
-          resolve(response.affectedLines)
+          resolve(response.result)
         }
       })
       this.once('error', (response) => {
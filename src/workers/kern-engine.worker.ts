@@ -24,24 +24,57 @@ export interface EditDelta {
   delete?: number
 }
 
+/** Old lines `[start, start + removed)` became lines `[start, end)` */
+export interface LineRange {
+  start: number
+  end: number
+  inserted: number
+  removed: number
+}
+
+/** Where the caret lands after an edit */
+export interface Caret {
+  line: number
+  col: number
+  offset: number
+}
+
+export interface EditResult {
+  lines: LineRange
+  caret: Caret
+}
+
+export interface MarkSpan {
+  start: number
+  end: number
+  key: string
+  value: unknown
+}
+
 export interface LineView {
   id: string
   content: string
+  char_len?: number
+  styled?: boolean
+  marks?: MarkSpan[]
+  block?: string
+  visual_rows?: number
 }
 
 export interface DocumentView {
   lines: LineView[]
   version: number
+  total_lines?: number
 }
 
 export type WorkerResponse =
   | { type: 'ready'; health: string }
   | { type: 'view'; view: DocumentView }
-  | { type: 'edited'; affectedLines: number[] }
+  | { type: 'edited'; result: EditResult }
   | { type: 'snapshot'; data: Uint8Array }
   | { type: 'loaded' }
   | { type: 'health'; message: string }
-  | { type: 'error'; message: string }
+  | { type: 'error'; message: string; code?: string }
 
 // Initialize the WASM engine
 async function initEngine() {
@@ -89,8 +122,8 @@ self.onmessage = async (event: MessageEvent<WorkerMessage>) => {
 
       case 'apply_edit': {
         if (!engine) throw new Error('Engine not initialized')
-        const affectedLines = engine.apply_edit(msg.delta) as number[]
-        const response: WorkerResponse = { type: 'edited', affectedLines }
+        const result = engine.apply_edit(msg.delta) as EditResult
+        const response: WorkerResponse = { type: 'edited', result }
         self.postMessage(response)
         break
       }
@@ -129,9 +162,12 @@ self.onmessage = async (event: MessageEvent<WorkerMessage>) => {
       }
     }
   } catch (error) {
+    // Engine methods throw `{ code, message }` objects
+    const engineError = error as { code?: string; message?: string }
     const response: WorkerResponse = {
       type: 'error',
-      message: `Worker error: ${error}`,
+      message: `Worker error: ${engineError?.message ?? error}`,
+      code: engineError?.code,
     }
     self.postMessage(response)
   }